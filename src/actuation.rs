@@ -0,0 +1,479 @@
+//! The only way to the servos. Scheduled feeds, feeds after a crash, the
+//! servo test and feeds asked for over the control socket or the API all go
+//! on a queue, and one worker carries them out one at a time, so two callers
+//! never drive the same PWM at once. Clients can list what is waiting and
+//! cancel it by id.
+
+use log::{error, info};
+use serde::{Deserialize, Serialize};
+use std::cmp::Reverse;
+use std::sync::mpsc;
+use std::sync::{Arc, Condvar, Mutex};
+use std::time::Duration;
+
+use crate::clock::Clock;
+use crate::config::Config;
+use crate::feeder::{self, Attempt, FeedOutcome, Feeder, HardwareRetry};
+use crate::hardware::{probe_hardware, Hardware};
+use crate::history::Trigger;
+use crate::schedule::Occasion;
+use crate::status::Status;
+use crate::Files;
+
+/// Which command goes first when several are waiting, highest last.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Priority {
+    Manual,
+    Scheduled,
+    SafetyClose,
+}
+
+#[derive(Clone)]
+pub enum Command {
+    Feed {
+        occasion: Box<Occasion>,
+        trigger: Trigger,
+        attempt: Attempt,
+    },
+    /// Closes the lid and disables the PWM, whatever state it was left in.
+    Close,
+}
+
+impl Command {
+    pub fn priority(&self) -> Priority {
+        match *self {
+            Command::Feed {
+                trigger: Trigger::Manual,
+                ..
+            } => Priority::Manual,
+            Command::Feed { .. } => Priority::Scheduled,
+            Command::Close => Priority::SafetyClose,
+        }
+    }
+
+    fn describe(&self) -> String {
+        match *self {
+            Command::Feed {
+                ref occasion,
+                trigger,
+                ..
+            } => format!("{} feed of {}", trigger.as_str(), occasion.id()),
+            Command::Close => String::from("safety close"),
+        }
+    }
+}
+
+/// How a command ended, sent back to whoever submitted it.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Completion {
+    Fed(FeedOutcome),
+    Closed {
+        error: Option<String>,
+    },
+    /// Cancelled or shut down before it was carried out.
+    Cancelled,
+}
+
+/// A command that hasn't started yet, as clients get to see it.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct Waiting {
+    pub id: u64,
+    pub command: String,
+}
+
+struct Pending {
+    id: u64,
+    command: Command,
+    reply: mpsc::Sender<Completion>,
+}
+
+impl Pending {
+    fn complete(self, completion: Completion) {
+        // nobody might be waiting for it any more, that's fine
+        let _ = self.reply.send(completion);
+    }
+}
+
+#[derive(Default)]
+struct Inner {
+    next_id: u64,
+    pending: Vec<Pending>,
+    closed: bool,
+}
+
+#[derive(Default)]
+struct Shared {
+    inner: Mutex<Inner>,
+    changed: Condvar,
+}
+
+/// Commands waiting for the worker. Clones share the same queue.
+#[derive(Clone, Default)]
+pub struct Queue {
+    shared: Arc<Shared>,
+}
+
+/// Where the completion of a submitted command turns up.
+pub struct Ticket {
+    pub id: u64,
+    reply: mpsc::Receiver<Completion>,
+}
+
+impl Ticket {
+    /// Blocks until the command has been carried out or cancelled.
+    pub fn wait(self) -> Completion {
+        self.reply.recv().unwrap_or(Completion::Cancelled)
+    }
+}
+
+/// What the worker is to do next.
+enum Next {
+    Command(Pending),
+    /// Nothing came in before the timeout.
+    Idle,
+    Closed,
+}
+
+impl Queue {
+    pub fn new() -> Queue {
+        Queue::default()
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, Inner> {
+        // a panic while holding the lock leaves the queue itself intact
+        self.shared.inner.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// Queues a command and wakes the worker. A closed queue cancels it
+    /// right away.
+    pub fn submit(&self, command: Command) -> Ticket {
+        let (sender, receiver) = mpsc::channel();
+        let mut inner = self.lock();
+        inner.next_id += 1;
+        let id = inner.next_id;
+        if inner.closed {
+            let _ = sender.send(Completion::Cancelled);
+        } else {
+            inner.pending.push(Pending {
+                id,
+                command,
+                reply: sender,
+            });
+            self.shared.changed.notify_all();
+        }
+        Ticket {
+            id,
+            reply: receiver,
+        }
+    }
+
+    /// Cancels a command that is still waiting. Returns false if it was
+    /// already taken, a feed in progress is left to finish.
+    pub fn cancel(&self, id: u64) -> bool {
+        let mut inner = self.lock();
+        match inner.pending.iter().position(|p| p.id == id) {
+            Some(index) => {
+                inner.pending.remove(index).complete(Completion::Cancelled);
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// The commands still waiting, in the order they will be carried out.
+    pub fn waiting(&self) -> Vec<Waiting> {
+        let inner = self.lock();
+        let mut pending: Vec<&Pending> = inner.pending.iter().collect();
+        pending.sort_by_key(|p| Reverse((p.command.priority(), Reverse(p.id))));
+        pending
+            .iter()
+            .map(|p| Waiting {
+                id: p.id,
+                command: p.command.describe(),
+            })
+            .collect()
+    }
+
+    /// Takes the command to carry out next, the highest priority first and
+    /// the oldest first within a priority.
+    fn take(inner: &mut Inner) -> Option<Pending> {
+        let index = inner
+            .pending
+            .iter()
+            .enumerate()
+            .max_by_key(|&(_, p)| (p.command.priority(), Reverse(p.id)))
+            .map(|(index, _)| index)?;
+        Some(inner.pending.remove(index))
+    }
+
+    /// Waits for the next command, for at most `timeout` if given.
+    fn next(&self, timeout: Option<Duration>) -> Next {
+        let mut inner = self.lock();
+        loop {
+            if let Some(pending) = Queue::take(&mut inner) {
+                return Next::Command(pending);
+            }
+            if inner.closed {
+                return Next::Closed;
+            }
+            inner = match timeout {
+                Some(timeout) => {
+                    let (inner, waited) = self
+                        .shared
+                        .changed
+                        .wait_timeout(inner, timeout)
+                        .unwrap_or_else(|e| e.into_inner());
+                    if waited.timed_out() {
+                        return Next::Idle;
+                    }
+                    inner
+                }
+                None => self
+                    .shared
+                    .changed
+                    .wait(inner)
+                    .unwrap_or_else(|e| e.into_inner()),
+            };
+        }
+    }
+
+    /// Cancels the feeds still waiting and refuses new commands, for when
+    /// feeding stops. Safety closes already queued are still carried out
+    /// before the worker finishes.
+    pub fn close(&self) {
+        let mut inner = self.lock();
+        inner.closed = true;
+        let (closes, feeds) = inner
+            .pending
+            .drain(..)
+            .partition(|p| p.command.priority() == Priority::SafetyClose);
+        inner.pending = closes;
+        for pending in feeds {
+            pending.complete(Completion::Cancelled);
+        }
+        self.shared.changed.notify_all();
+    }
+}
+
+/// Owns the hardware: probes it, brings it back after it failed and carries
+/// out the queued commands. The config and status are shared with the
+/// daemon, which may reload the one and report the other meanwhile.
+pub struct Worker {
+    pub files: Files,
+    pub config: Arc<Mutex<Config>>,
+    pub status: Arc<Mutex<Status>>,
+}
+
+impl Worker {
+    /// Carries out queued commands one at a time until the queue is closed
+    /// and empty. Run only one worker, on a thread of its own, for the
+    /// hardware.
+    pub fn run(&self, queue: &Queue, clock: &dyn Clock, hardware: &dyn Hardware) {
+        let mut retry = HardwareRetry::new();
+        {
+            let mut status = self.status.lock().unwrap();
+            feeder::note_hardware(&self.files, &mut status, probe_hardware(hardware));
+            info!(hardware = status.hardware.as_str(); "Hardware probed");
+        }
+        loop {
+            let now = clock.now();
+            let probe = retry.next_probe(&self.status.lock().unwrap(), now);
+            let timeout = probe.map(|due| (due - now).to_std().unwrap_or_default());
+            match queue.next(timeout) {
+                Next::Command(pending) => {
+                    let completion = self.execute(&pending.command, clock, hardware);
+                    pending.complete(completion);
+                }
+                Next::Idle => {
+                    let mut status = self.status.lock().unwrap();
+                    retry.probe_if_due(&self.files, &mut status, hardware, clock.now());
+                }
+                Next::Closed => return,
+            }
+        }
+    }
+
+    fn execute(&self, command: &Command, clock: &dyn Clock, hardware: &dyn Hardware) -> Completion {
+        let config = self.config.lock().unwrap().clone();
+        let feeder = Feeder {
+            config: &config,
+            files: &self.files,
+            clock,
+            hardware,
+        };
+        match *command {
+            Command::Feed {
+                ref occasion,
+                trigger,
+                attempt,
+            } => {
+                // Feeds against a copy so that the status can still be read
+                // while the lid is open. Only the worker writes it.
+                let mut status = self.status.lock().unwrap().clone();
+                let report = feeder.feed_occasion(occasion, trigger, attempt, &mut status);
+                *self.status.lock().unwrap() = status;
+                Completion::Fed(report)
+            }
+            Command::Close => match feeder::close_lids(hardware, clock) {
+                Ok(()) => {
+                    info!("Lid closed on request");
+                    Completion::Closed { error: None }
+                }
+                Err(e) => {
+                    error!(error:% = e; "Failed to close lid");
+                    Completion::Closed {
+                        error: Some(e.to_string()),
+                    }
+                }
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+fn feed(trigger: Trigger) -> Command {
+    use chrono::prelude::*;
+    Command::Feed {
+        occasion: Box::new(Occasion {
+            time: Local.ymd(1970, 1, 1).and_hms(4, 25, 0),
+            enabled_weekdays: vec![Weekday::Mon],
+            opened_time_servo1: 320,
+            opened_time_servo2: 280,
+            jiggle_servo1: None,
+            jiggle_servo2: None,
+        }),
+        trigger,
+        attempt: Attempt::first(Local::now()),
+    }
+}
+
+#[cfg(test)]
+fn take(queue: &Queue) -> Option<u64> {
+    Queue::take(&mut queue.lock()).map(|p| p.id)
+}
+
+/// Runs a worker on this thread, against traced hardware and files in a
+/// directory of their own, while `submit` queues commands on another.
+#[cfg(test)]
+fn with_worker<T: Send + 'static>(
+    name: &str,
+    submit: impl FnOnce(&Queue) -> T + Send + 'static,
+) -> (T, crate::trace::Trace) {
+    use crate::clock::VirtualClock;
+    use crate::trace::{Trace, TracingHardware};
+    use chrono::prelude::*;
+
+    let dir = std::env::temp_dir().join(format!("picat-{}-{}", name, std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(&dir).unwrap();
+    let clock = VirtualClock::new(Local.ymd(2019, 9, 2).and_hms(4, 25, 0));
+    let trace = Trace::new();
+    let hardware = TracingHardware {
+        trace: &trace,
+        clock: &clock,
+    };
+    let worker = Worker {
+        files: Files::in_dir(&dir),
+        config: Arc::new(Mutex::new(Config::default())),
+        status: Arc::new(Mutex::new(Status::new())),
+    };
+    let queue = Queue::new();
+    let submitter = {
+        let queue = queue.clone();
+        std::thread::spawn(move || {
+            let result = submit(&queue);
+            queue.close();
+            result
+        })
+    };
+    worker.run(&queue, &clock, &hardware);
+    let result = submitter.join().unwrap();
+    std::fs::remove_dir_all(&dir).unwrap();
+    (result, trace)
+}
+
+#[test]
+fn commands_are_taken_by_priority_then_in_order() {
+    let queue = Queue::new();
+    let manual = queue.submit(feed(Trigger::Manual));
+    let scheduled = queue.submit(feed(Trigger::Scheduled));
+    let recovery = queue.submit(feed(Trigger::Recovery));
+    let close = queue.submit(Command::Close);
+
+    let listed: Vec<u64> = queue.waiting().iter().map(|w| w.id).collect();
+    let order: Vec<u64> = std::iter::from_fn(|| take(&queue)).collect();
+    assert_eq!(vec![close.id, scheduled.id, recovery.id, manual.id], order);
+    assert_eq!(order, listed);
+    assert!(queue.waiting().is_empty());
+}
+
+#[test]
+fn cancelled_commands_are_not_carried_out() {
+    let queue = Queue::new();
+    let first = queue.submit(feed(Trigger::Manual));
+    let second = queue.submit(feed(Trigger::Manual));
+
+    assert!(queue.cancel(first.id));
+    assert!(!queue.cancel(first.id));
+    assert_eq!(
+        vec![Waiting {
+            id: second.id,
+            command: String::from("manual feed of 04:25"),
+        }],
+        queue.waiting()
+    );
+    assert_eq!(Some(second.id), take(&queue));
+    assert_eq!(None, take(&queue));
+    assert_eq!(Completion::Cancelled, first.wait());
+}
+
+#[test]
+fn closed_queue_cancels_feeds_but_not_safety_closes() {
+    let queue = Queue::new();
+    let waiting = queue.submit(feed(Trigger::Scheduled));
+    let close = queue.submit(Command::Close);
+    queue.close();
+    let late = queue.submit(Command::Close);
+
+    assert_eq!(Some(close.id), take(&queue));
+    assert_eq!(None, take(&queue));
+    assert_eq!(Completion::Cancelled, waiting.wait());
+    assert_eq!(Completion::Cancelled, late.wait());
+}
+
+#[test]
+fn worker_feeds_what_was_submitted_from_another_thread() {
+    let (completion, trace) = with_worker("actuation-feed", |queue| {
+        queue.submit(feed(Trigger::Manual)).wait()
+    });
+
+    match completion {
+        Completion::Fed(FeedOutcome {
+            report: Some(report),
+            ..
+        }) => {
+            assert_eq!(None, report.error);
+            assert_eq!(320, report.open_ms);
+        }
+        completion => panic!("Not fed: {:?}", completion),
+    }
+    assert!(trace.samples().iter().any(|s| s.enabled));
+}
+
+#[test]
+fn close_command_closes_and_disables_the_lid() {
+    use crate::hardware::PULSE_CLOSED_US;
+
+    let (completion, trace) = with_worker("actuation-close", |queue| {
+        queue.submit(Command::Close).wait()
+    });
+
+    assert_eq!(Completion::Closed { error: None }, completion);
+    let samples = trace.samples();
+    let last = samples.last().unwrap();
+    assert!(!last.enabled);
+    assert!(samples
+        .iter()
+        .filter(|s| s.enabled)
+        .all(|s| s.pulse_us == PULSE_CLOSED_US));
+}
@@ -0,0 +1,10 @@
+use log::warn;
+
+use crate::sd_notify::Notifier;
+
+/// Reports something a human should look at, in the log and as the systemd
+/// service status.
+pub fn raise(message: &str) {
+    warn!(alert = true; "{}", message);
+    Notifier::from_env().status(&format!("ALERT: {}", message));
+}
@@ -0,0 +1,330 @@
+//! The daemon's HTTP API. Requests are passed on to the feeder loop like
+//! those on the control socket, so both go through the same checks, storage
+//! and actuation queue.
+//!
+//! ```text
+//! GET    /status
+//! GET    /occasions
+//! POST   /occasions            an occasion like in schedule.json
+//! PUT    /occasions/{HH:MM}
+//! DELETE /occasions/{HH:MM}
+//! POST   /feed                 {"ms": 300}, optional
+//! POST   /skip-next
+//! POST   /pause
+//! POST   /resume
+//! POST   /breaker/reset
+//! GET    /queue                feeds waiting for the hardware
+//! DELETE /queue/{id}
+//! GET    /history              ?from=YYYY-MM-DD&to=YYYY-MM-DD&format=json|csv|text
+//! GET    /config
+//! PUT    /config
+//! ```
+
+use log::warn;
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use std::error::Error;
+use std::net::{SocketAddr, ToSocketAddrs};
+use std::sync::mpsc;
+use std::thread;
+use tiny_http::{Header, Method, Server};
+
+use crate::control::{self, Call, Request, Response};
+use crate::report::FeedError;
+use crate::{history, Files};
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct HttpConfig {
+    /// Where to listen, like "192.168.1.187:8080". Only this machine can
+    /// reach the default.
+    #[serde(default = "default_address")]
+    pub address: String,
+    /// Required as `Authorization: Bearer <token>` when set. An address that
+    /// isn't loopback is only served with one.
+    #[serde(default)]
+    pub token: Option<String>,
+}
+
+fn default_address() -> String {
+    String::from("127.0.0.1:8080")
+}
+
+impl HttpConfig {
+    /// Refuses an address that isn't loopback without a token.
+    pub fn validate(&self) -> Result<(), String> {
+        let addresses = self
+            .address
+            .to_socket_addrs()
+            .map_err(|e| format!("Invalid address {}: {}", self.address, e))?;
+        let loopback = addresses
+            .into_iter()
+            .all(|address| address.ip().is_loopback());
+        if !loopback && self.token.is_none() {
+            return Err(format!(
+                "{} needs a token, anyone who can reach it could feed",
+                self.address
+            ));
+        }
+        Ok(())
+    }
+}
+
+/// What goes back to the client.
+struct Reply {
+    status: u16,
+    content_type: &'static str,
+    body: String,
+}
+
+impl Reply {
+    fn json(status: u16, body: &serde_json::Value) -> Reply {
+        Reply {
+            status,
+            content_type: "application/json",
+            body: body.to_string(),
+        }
+    }
+
+    fn error(status: u16, message: &str) -> Reply {
+        Reply::json(status, &json!({ "error": message }))
+    }
+}
+
+#[derive(Deserialize, Default)]
+struct FeedBody {
+    #[serde(default)]
+    ms: Option<u64>,
+}
+
+/// Serves the API as `config` says in the background and returns where it
+/// listens, which tells the port when asked for port 0.
+/// The history is read from `files`, everything else is asked of the feeder
+/// loop through `calls`.
+pub fn serve(
+    config: &HttpConfig,
+    files: Files,
+    calls: mpsc::Sender<Call>,
+) -> Result<SocketAddr, Box<dyn Error>> {
+    config.validate()?;
+    let server = Server::http(&config.address).map_err(|e| e.to_string())?;
+    let local = server
+        .server_addr()
+        .to_ip()
+        .ok_or("Not listening on an IP address")?;
+    let token = config.token.clone();
+    thread::spawn(move || {
+        for mut request in server.incoming_requests() {
+            let calls = calls.clone();
+            let files = files.clone();
+            let token = token.clone();
+            thread::spawn(move || {
+                let mut body = String::new();
+                let reply = if !authorized(&request, token.as_deref()) {
+                    Reply::error(401, "Missing or wrong token")
+                } else {
+                    match request.as_reader().read_to_string(&mut body) {
+                        Ok(_) => route(request.method(), request.url(), &body, &files, &calls),
+                        Err(e) => Reply::error(400, &e.to_string()),
+                    }
+                };
+                let content_type = Header::from_bytes("Content-Type", reply.content_type).unwrap();
+                let response = tiny_http::Response::from_string(reply.body)
+                    .with_status_code(reply.status)
+                    .with_header(content_type);
+                if let Err(e) = request.respond(response) {
+                    warn!(error:% = e; "Failed to answer HTTP request");
+                }
+            });
+        }
+    });
+    Ok(local)
+}
+
+/// Whether the request carries the token, if one is needed.
+fn authorized(request: &tiny_http::Request, token: Option<&str>) -> bool {
+    let token = match token {
+        Some(token) => token,
+        None => return true,
+    };
+    let expected = format!("Bearer {}", token);
+    request
+        .headers()
+        .iter()
+        .filter(|header| header.field.equiv("Authorization"))
+        .any(|header| same(header.value.as_str().as_bytes(), expected.as_bytes()))
+}
+
+/// Compares in the same time wherever the first difference is, so the time
+/// an answer takes doesn't give the token away bit by bit.
+fn same(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |diff, (x, y)| diff | (x ^ y)) == 0
+}
+
+/// Decodes `%XX` escapes, and `+` as a space where `plus_is_space`, as in
+/// query strings.
+fn percent_decode(text: &str, plus_is_space: bool) -> Result<String, String> {
+    let bytes = text.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut index = 0;
+    while index < bytes.len() {
+        match bytes[index] {
+            b'%' => {
+                let byte = text
+                    .get(index + 1..index + 3)
+                    .and_then(|hex| u8::from_str_radix(hex, 16).ok())
+                    .ok_or_else(|| format!("Invalid percent-encoding in {}", text))?;
+                decoded.push(byte);
+                index += 3;
+            }
+            b'+' if plus_is_space => {
+                decoded.push(b' ');
+                index += 1;
+            }
+            byte => {
+                decoded.push(byte);
+                index += 1;
+            }
+        }
+    }
+    String::from_utf8(decoded).map_err(|_| format!("Invalid percent-encoding in {}", text))
+}
+
+fn route(
+    method: &Method,
+    url: &str,
+    body: &str,
+    files: &Files,
+    calls: &mpsc::Sender<Call>,
+) -> Reply {
+    let (path, query) = match url.find('?') {
+        Some(index) => (&url[..index], &url[index + 1..]),
+        None => (url, ""),
+    };
+    // decoded segment by segment, an escaped slash belongs to its segment
+    let segments: Result<Vec<String>, String> = path
+        .trim_matches('/')
+        .split('/')
+        .map(|segment| percent_decode(segment, false))
+        .collect();
+    let segments = match segments {
+        Ok(segments) => segments,
+        Err(e) => return Reply::error(400, &e),
+    };
+    let segments: Vec<&str> = segments.iter().map(String::as_str).collect();
+    let ask = |request: Request| reply(control::call(calls, request), 200);
+
+    match (method, segments.as_slice()) {
+        (Method::Get, ["status"]) => ask(Request::Status),
+        (Method::Get, ["occasions"]) => ask(Request::GetSchedule),
+        (Method::Post, ["occasions"]) => match serde_json::from_str(body) {
+            Ok(occasion) => reply(control::call(calls, Request::AddOccasion { occasion }), 201),
+            Err(e) => Reply::error(400, &format!("Invalid occasion: {}", e)),
+        },
+        (Method::Put, ["occasions", id]) => match serde_json::from_str(body) {
+            Ok(occasion) => ask(Request::UpdateOccasion {
+                id: id.to_string(),
+                occasion,
+            }),
+            Err(e) => Reply::error(400, &format!("Invalid occasion: {}", e)),
+        },
+        (Method::Delete, ["occasions", id]) => ask(Request::DeleteOccasion { id: id.to_string() }),
+        (Method::Post, ["feed"]) => {
+            let feed = if body.trim().is_empty() {
+                Ok(FeedBody::default())
+            } else {
+                serde_json::from_str::<FeedBody>(body)
+            };
+            match feed {
+                Ok(feed) => ask(Request::FeedNow { ms: feed.ms }),
+                Err(e) => Reply::error(400, &format!("Invalid feed: {}", e)),
+            }
+        }
+        (Method::Post, ["skip-next"]) => ask(Request::SkipNext),
+        (Method::Post, ["pause"]) => ask(Request::Pause),
+        (Method::Post, ["resume"]) => ask(Request::Resume),
+        (Method::Post, ["breaker", "reset"]) => ask(Request::ResetBreaker),
+        (Method::Get, ["queue"]) => ask(Request::Queue),
+        (Method::Delete, ["queue", id]) => match id.parse() {
+            Ok(id) => ask(Request::Cancel { id }),
+            Err(_) => Reply::error(400, &format!("Invalid command id {}", id)),
+        },
+        (Method::Get, ["history"]) => show_history(&files.history, query),
+        (Method::Get, ["config"]) => ask(Request::GetConfig),
+        (Method::Put, ["config"]) => match serde_json::from_str(body) {
+            Ok(config) => ask(Request::SetConfig { config }),
+            Err(e) => Reply::error(400, &format!("Invalid config: {}", e)),
+        },
+        _ => Reply::error(404, &format!("No such endpoint {} {}", method, path)),
+    }
+}
+
+fn reply(response: Response, success: u16) -> Reply {
+    match response {
+        Response::Done { message } => Reply::json(success, &json!({ "message": message })),
+        Response::Status {
+            status,
+            paused,
+            skip_next,
+        } => Reply::json(
+            200,
+            &json!({ "status": status, "paused": paused, "skip_next": skip_next }),
+        ),
+        // a failed feed still answers with its report, saying how far it got
+        Response::Fed { report } => {
+            let status = match report.error {
+                None => 200,
+                Some(FeedError::HardwareUnavailable { .. }) | Some(FeedError::Aborted) => 503,
+                Some(_) => 500,
+            };
+            Reply::json(status, &json!(report))
+        }
+        Response::Queue { commands } => Reply::json(200, &json!(commands)),
+        Response::Schedule { schedule } => Reply::json(200, &schedule),
+        Response::Config { config } => Reply::json(200, &config),
+        Response::NotFound { message } => Reply::error(404, &message),
+        Response::Error { message } => Reply::error(400, &message),
+        Response::Failed { message } => Reply::error(500, &message),
+    }
+}
+
+/// Answers like `picat history` does, taking its options as query
+/// parameters.
+fn show_history(file_path: &str, query: &str) -> Reply {
+    let mut args = Vec::new();
+    for pair in query.split('&').filter(|pair| !pair.is_empty()) {
+        let mut pair = pair.splitn(2, '=');
+        let name = percent_decode(pair.next().unwrap_or_default(), true);
+        let value = percent_decode(pair.next().unwrap_or_default(), true);
+        match (name, value) {
+            (Ok(name), Ok(value)) => {
+                args.push(format!("--{}", name));
+                args.push(value);
+            }
+            (Err(e), _) | (_, Err(e)) => return Reply::error(400, &e),
+        }
+    }
+    let mut query = match history::Query::parse(&args) {
+        Ok(query) => query,
+        Err(e) => return Reply::error(400, &e),
+    };
+    if !args.iter().any(|arg| arg == "--format") {
+        query.format = history::Format::Json;
+    }
+    let entries = match history::select(file_path, &query) {
+        Ok(entries) => entries,
+        Err(e) => return Reply::error(500, &e.to_string()),
+    };
+    match query.format {
+        history::Format::Json => Reply::json(200, &json!(entries)),
+        history::Format::Csv => Reply {
+            status: 200,
+            content_type: "text/csv",
+            body: history::to_csv(&entries),
+        },
+        history::Format::Text => Reply {
+            status: 200,
+            content_type: "text/plain",
+            body: history::to_text(&entries),
+        },
+    }
+}
@@ -0,0 +1,43 @@
+use std::time::Duration;
+
+/// Doubles the wait after every failed attempt, up to a limit.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Backoff {
+    initial: Duration,
+    max: Duration,
+    current: Duration,
+}
+
+impl Backoff {
+    pub fn new(initial: Duration, max: Duration) -> Backoff {
+        Backoff {
+            initial,
+            max,
+            current: initial,
+        }
+    }
+
+    /// The wait before the next attempt, growing the one after it.
+    pub fn next_delay(&mut self) -> Duration {
+        let delay = self.current;
+        self.current = (self.current * 2).min(self.max);
+        delay
+    }
+
+    /// Starts over from the initial wait, after an attempt worked.
+    pub fn reset(&mut self) {
+        self.current = self.initial;
+    }
+}
+
+#[test]
+fn doubles_up_to_the_limit() {
+    let mut backoff = Backoff::new(Duration::from_secs(10), Duration::from_secs(30));
+
+    assert_eq!(Duration::from_secs(10), backoff.next_delay());
+    assert_eq!(Duration::from_secs(20), backoff.next_delay());
+    assert_eq!(Duration::from_secs(30), backoff.next_delay());
+    assert_eq!(Duration::from_secs(30), backoff.next_delay());
+    backoff.reset();
+    assert_eq!(Duration::from_secs(10), backoff.next_delay());
+}
@@ -0,0 +1,42 @@
+//! Checks the schedule and config in the working directory without touching
+//! the hardware, so mistakes show up before they reach the Pi.
+
+use std::process;
+
+use picat::{check, config, persistant_schedule_storage};
+use picat::{CONFIG_FILE_NAME, SCHEDULE_FILE_NAME};
+
+fn main() {
+    let mut problems = Vec::new();
+
+    let schedule = match persistant_schedule_storage::load(SCHEDULE_FILE_NAME) {
+        Ok(schedule) => Some(schedule),
+        Err(e) => {
+            problems.push(format!("{}: {}", SCHEDULE_FILE_NAME, e));
+            None
+        }
+    };
+    let config = match config::load(CONFIG_FILE_NAME) {
+        Ok(config) => config,
+        Err(e) => {
+            problems.push(format!(
+                "{}: {}, checking against defaults",
+                CONFIG_FILE_NAME, e
+            ));
+            config::Config::default()
+        }
+    };
+
+    problems.extend(check::config_problems(&config));
+    if let Some(schedule) = schedule {
+        problems.extend(check::schedule_problems(&schedule, &config));
+    }
+
+    for problem in problems.iter() {
+        println!("{}", problem);
+    }
+    if !problems.is_empty() {
+        process::exit(1);
+    }
+    println!("{} and {} look fine", SCHEDULE_FILE_NAME, CONFIG_FILE_NAME);
+}
@@ -0,0 +1,107 @@
+//! Talks to the running `picat` daemon over its control socket in the
+//! state directory.
+
+use std::env;
+use std::error::Error;
+use std::fs;
+use std::process;
+
+use picat::control::{self, Request, Response};
+use picat::Files;
+
+const USAGE: &str = "Usage: picatctl <command>
+
+Commands:
+  status               how feeding is going
+  feed-now [MS]        feed right away, for MS ms or as much as the next feed
+  skip-next            leave out the next scheduled feed
+  reset-breaker        let feeding go on after the circuit breaker tripped
+  queue                list the feeds waiting for the hardware
+  cancel ID            cancel a waiting feed
+  pause                stop scheduled feeding until resumed
+  resume               carry on with scheduled feeding
+  reload               reload config.json and schedule.json
+  schedule get         print the schedule
+  schedule set FILE    check and replace the schedule with the one in FILE
+  config get           print the config
+  config set FILE      check and replace the config with the one in FILE";
+
+fn parse(args: &[String]) -> Result<Request, Box<dyn Error>> {
+    let args: Vec<&str> = args.iter().map(String::as_str).collect();
+    Ok(match args.as_slice() {
+        ["status"] => Request::Status,
+        ["feed-now"] => Request::FeedNow { ms: None },
+        ["feed-now", ms] => Request::FeedNow {
+            ms: Some(ms.parse().map_err(|_| format!("Invalid duration {}", ms))?),
+        },
+        ["skip-next"] => Request::SkipNext,
+        ["reset-breaker"] => Request::ResetBreaker,
+        ["queue"] => Request::Queue,
+        ["cancel", id] => Request::Cancel {
+            id: id
+                .parse()
+                .map_err(|_| format!("Invalid command id {}", id))?,
+        },
+        ["pause"] => Request::Pause,
+        ["resume"] => Request::Resume,
+        ["reload"] => Request::Reload,
+        ["schedule", "get"] => Request::GetSchedule,
+        ["schedule", "set", file] => Request::SetSchedule {
+            schedule: serde_json::from_str(&fs::read_to_string(file)?)?,
+        },
+        ["config", "get"] => Request::GetConfig,
+        ["config", "set", file] => Request::SetConfig {
+            config: serde_json::from_str(&fs::read_to_string(file)?)?,
+        },
+        _ => return Err(USAGE.into()),
+    })
+}
+
+fn run(args: &[String]) -> Result<(), Box<dyn Error>> {
+    let request = parse(args)?;
+    let socket = Files::default().socket;
+    let response = control::send(&socket, &request)
+        .map_err(|e| format!("Failed to reach picat on {}: {}", socket, e))?;
+    match response {
+        Response::Done { message } => println!("{}", message),
+        Response::Status {
+            status,
+            paused,
+            skip_next,
+        } => {
+            println!("{}", status);
+            if paused {
+                println!("scheduled feeding is paused");
+            }
+            if let Some(due) = skip_next {
+                println!("skipping the feed at {}", due);
+            }
+        }
+        Response::Fed { report } => println!(
+            "{}: {}, opened {} ms of {} ms",
+            report.hopper,
+            report.outcome(),
+            report.open_ms,
+            report.requested_ms
+        ),
+        Response::Queue { commands } => {
+            for waiting in commands {
+                println!("{:>4}  {}", waiting.id, waiting.command);
+            }
+        }
+        Response::Schedule { schedule } => println!("{}", serde_json::to_string_pretty(&schedule)?),
+        Response::Config { config } => println!("{}", serde_json::to_string_pretty(&config)?),
+        Response::NotFound { message }
+        | Response::Error { message }
+        | Response::Failed { message } => return Err(message.into()),
+    }
+    Ok(())
+}
+
+fn main() {
+    let args: Vec<String> = env::args().skip(1).collect();
+    if let Err(e) = run(&args) {
+        eprintln!("{}", e);
+        process::exit(1);
+    }
+}
@@ -0,0 +1,123 @@
+use chrono::prelude::*;
+use serde::{Deserialize, Serialize};
+use std::fs::File;
+use std::io::prelude::*;
+
+use crate::config::BreakerConfig;
+
+#[derive(Debug, PartialEq)]
+pub enum Decision {
+    Allowed,
+    JustTripped,
+    Tripped,
+}
+
+/// Stops all dispensing once too many dispenses happen within a short window.
+/// Stays tripped until explicitly reset.
+#[derive(Serialize, Deserialize, Default)]
+pub struct CircuitBreaker {
+    recent_dispenses: Vec<i64>,
+    tripped_at: Option<i64>,
+}
+
+impl CircuitBreaker {
+    pub fn new() -> CircuitBreaker {
+        CircuitBreaker::default()
+    }
+
+    pub fn is_tripped(&self) -> bool {
+        self.tripped_at.is_some()
+    }
+
+    pub fn tripped_at(&self) -> Option<DateTime<Local>> {
+        self.tripped_at.map(|t| Local.timestamp(t, 0))
+    }
+
+    /// Decides whether a dispense may happen at `now`, and counts it if so.
+    pub fn allow_dispense(&mut self, now: DateTime<Local>, config: &BreakerConfig) -> Decision {
+        if self.is_tripped() {
+            return Decision::Tripped;
+        }
+
+        let window_start = now.timestamp() - i64::from(config.window_minutes) * 60;
+        self.recent_dispenses.retain(|&t| t > window_start);
+
+        if self.recent_dispenses.len() >= config.max_dispenses as usize {
+            self.tripped_at = Some(now.timestamp());
+            return Decision::JustTripped;
+        }
+        self.recent_dispenses.push(now.timestamp());
+        Decision::Allowed
+    }
+
+    /// Stops all dispensing from `now` on, until reset.
+    pub fn trip(&mut self, now: DateTime<Local>) {
+        self.tripped_at = Some(now.timestamp());
+    }
+
+    pub fn reset(&mut self) {
+        self.recent_dispenses.clear();
+        self.tripped_at = None;
+    }
+}
+
+pub fn load(file_path: &str) -> Result<CircuitBreaker, std::io::Error> {
+    let mut file = File::open(file_path)?;
+    let mut file_content = String::new();
+    file.read_to_string(&mut file_content)?;
+    Ok(serde_json::from_str(&file_content)?)
+}
+
+#[test]
+fn trips_after_max_dispenses() {
+    let config = BreakerConfig {
+        max_dispenses: 2,
+        window_minutes: 10,
+    };
+    let mut breaker = CircuitBreaker::new();
+    let start = Local.ymd(2019, 9, 1).and_hms(7, 30, 0);
+
+    assert_eq!(Decision::Allowed, breaker.allow_dispense(start, &config));
+    assert_eq!(
+        Decision::Allowed,
+        breaker.allow_dispense(start + chrono::Duration::minutes(1), &config)
+    );
+    assert_eq!(
+        Decision::JustTripped,
+        breaker.allow_dispense(start + chrono::Duration::minutes(2), &config)
+    );
+    assert_eq!(
+        Decision::Tripped,
+        breaker.allow_dispense(start + chrono::Duration::hours(2), &config)
+    );
+}
+
+#[test]
+fn old_dispenses_leave_window() {
+    let config = BreakerConfig {
+        max_dispenses: 1,
+        window_minutes: 10,
+    };
+    let mut breaker = CircuitBreaker::new();
+    let start = Local.ymd(2019, 9, 1).and_hms(7, 30, 0);
+
+    assert_eq!(Decision::Allowed, breaker.allow_dispense(start, &config));
+    assert_eq!(
+        Decision::Allowed,
+        breaker.allow_dispense(start + chrono::Duration::minutes(10), &config)
+    );
+}
+
+#[test]
+fn reset_clears_trip() {
+    let config = BreakerConfig {
+        max_dispenses: 0,
+        window_minutes: 10,
+    };
+    let mut breaker = CircuitBreaker::new();
+    let now = Local.ymd(2019, 9, 1).and_hms(7, 30, 0);
+
+    assert_eq!(Decision::JustTripped, breaker.allow_dispense(now, &config));
+    breaker.reset();
+    assert!(!breaker.is_tripped());
+}
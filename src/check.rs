@@ -0,0 +1,179 @@
+//! The checks `picat-check` runs on the schedule and config, shared with
+//! everything else that accepts a new one.
+
+use crate::config::Config;
+use crate::hardware::{PULSE_CLOSED_US, PULSE_OPEN_US, PULSE_PASSED_US, SERVO1_LIMITS};
+use crate::schedule::{Occasion, Schedule};
+use crate::servo::Servo;
+
+/// The servo jiggle patterns are checked against, without any hardware.
+fn servo1(config: &Config) -> Servo<'static> {
+    Servo::new(
+        SERVO1_LIMITS,
+        PULSE_CLOSED_US,
+        PULSE_OPEN_US,
+        PULSE_PASSED_US,
+        config.hopper(0).motion,
+        None,
+    )
+}
+
+/// Describes what is wrong with the config.
+pub fn config_problems(config: &Config) -> Vec<String> {
+    let mut problems = Vec::new();
+    let hopper = config.hopper(0);
+    if let Err(e) = hopper.jiggle.validate(&servo1(config)) {
+        problems.push(format!("jiggle pattern of {}: {}", hopper.name, e));
+    }
+    if let Some(Err(e)) = config.http.as_ref().map(|http| http.validate()) {
+        problems.push(format!("http: {}", e));
+    }
+    for hopper in config.hoppers.iter() {
+        if let Some(Err(e)) = hopper.daily_max.map(|ration| ration.validate()) {
+            problems.push(format!("daily_max of {}: {}", hopper.name, e));
+        }
+        if let Some(ref watchdog) = config.watchdog {
+            if hopper.retry.delay_s > watchdog.max_heartbeat_age_seconds {
+                problems.push(format!(
+                    "retry delay_s of {} is longer than the watchdog's max_heartbeat_age_seconds of {}",
+                    hopper.name, watchdog.max_heartbeat_age_seconds
+                ));
+            }
+        }
+    }
+    problems
+}
+
+/// Describes what is wrong with feeding the occasion with the config, for
+/// scheduled feeds and those asked for right away alike.
+pub fn occasion_problems(occasion: &Occasion, config: &Config) -> Vec<String> {
+    let mut problems = Vec::new();
+    let servo1 = servo1(config);
+    if let Some(Err(e)) = occasion.jiggle_servo1.as_ref().map(|j| j.validate(&servo1)) {
+        problems.push(format!("jiggle pattern of {}: {}", occasion.id(), e));
+    }
+    let open_ms = [occasion.opened_time_servo1, occasion.opened_time_servo2];
+    for (index, ms) in open_ms.iter().enumerate() {
+        let hopper = config.hopper(index);
+        if *ms > hopper.max_open_ms {
+            problems.push(format!(
+                "{} opens {} for {} ms, longer than its max_open_ms of {}",
+                occasion.id(),
+                hopper.name,
+                ms,
+                hopper.max_open_ms
+            ));
+        }
+    }
+    problems
+}
+
+/// Describes what is wrong with the schedule, or likely a mistake, when fed
+/// with the config.
+pub fn schedule_problems(schedule: &Schedule, config: &Config) -> Vec<String> {
+    let mut problems = schedule.lint();
+    for occasion in schedule.get_times().iter() {
+        problems.extend(occasion_problems(occasion, config));
+    }
+    problems
+}
+
+/// Describes what keeps the schedule from being fed with the config, leaving
+/// out the likely mistakes `Schedule::lint` only warns about.
+pub fn settings_problems(schedule: &Schedule, config: &Config) -> Vec<String> {
+    let mut problems = config_problems(config);
+    for occasion in schedule.get_times().iter() {
+        problems.extend(occasion_problems(occasion, config));
+    }
+    problems
+}
+
+/// Sorts what is wrong with changing `schedule` into `changed` into what
+/// refuses the change and what is only warned about. The change is refused
+/// for occasions that can't be fed with the config and for the likely
+/// mistakes it brings in itself, while those already scheduled, like the
+/// repeated 11:30 of the default schedule, don't hold up other changes.
+pub fn schedule_change_problems(
+    schedule: &Schedule,
+    changed: &Schedule,
+    config: &Config,
+) -> (Vec<String>, Vec<String>) {
+    let before = schedule.lint();
+    let scheduled = |schedule: &Schedule, id: &str| {
+        schedule.get_times().iter().filter(|o| o.id() == id).count()
+    };
+    let mut refused = Vec::new();
+    for occasion in changed.get_times().iter() {
+        refused.extend(occasion_problems(occasion, config));
+        refused.extend(occasion.lint().into_iter().filter(|p| !before.contains(p)));
+        let id = occasion.id();
+        let count = scheduled(changed, &id);
+        let repeated = format!("{} is scheduled {} times", id, count);
+        if count > 1 && count > scheduled(schedule, &id) && !refused.contains(&repeated) {
+            refused.push(repeated);
+        }
+    }
+    let warnings = changed
+        .lint()
+        .into_iter()
+        .filter(|p| !refused.contains(p))
+        .collect();
+    (refused, warnings)
+}
+
+#[test]
+fn retry_delay_must_fit_in_the_heartbeat_age() {
+    let mut config = Config {
+        watchdog: Some(serde_json::from_str("{\"max_heartbeat_age_seconds\": 150}").unwrap()),
+        ..Config::default()
+    };
+    assert!(config_problems(&config).is_empty());
+
+    config.hoppers[0].retry.delay_s = 151;
+    assert_eq!(
+        vec![format!(
+            "retry delay_s of {} is longer than the watchdog's max_heartbeat_age_seconds of 150",
+            config.hoppers[0].name
+        )],
+        config_problems(&config)
+    );
+    config.watchdog = None;
+    assert!(config_problems(&config).is_empty());
+}
+
+#[test]
+fn occasions_may_not_open_longer_than_the_hopper_allows() {
+    use chrono::prelude::*;
+
+    let mut schedule = Schedule::new();
+    schedule.push(Occasion {
+        time: Local.ymd(1970, 1, 1).and_hms(7, 30, 0),
+        enabled_weekdays: vec![Weekday::Mon],
+        opened_time_servo1: 3000,
+        opened_time_servo2: 3001,
+        jiggle_servo1: None,
+        jiggle_servo2: None,
+    });
+
+    assert_eq!(
+        vec!["07:30 opens servo2 for 3001 ms, longer than its max_open_ms of 3000"],
+        schedule_problems(&schedule, &Config::default())
+    );
+}
+
+#[test]
+fn http_beyond_loopback_needs_a_token() {
+    let mut config = Config {
+        http: Some(serde_json::from_str("{\"address\": \"0.0.0.0:8080\"}").unwrap()),
+        ..Config::default()
+    };
+    assert_eq!(
+        vec!["http: 0.0.0.0:8080 needs a token, anyone who can reach it could feed"],
+        config_problems(&config)
+    );
+
+    config.http.as_mut().unwrap().token = Some(String::from("s3cret"));
+    assert!(config_problems(&config).is_empty());
+    config.http = Some(serde_json::from_str("{}").unwrap());
+    assert!(config_problems(&config).is_empty());
+}
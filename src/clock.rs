@@ -0,0 +1,134 @@
+use chrono::prelude::*;
+use std::sync::Mutex;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::signals::{self, Event, Terminated};
+
+/// Where the feeder gets the time from and how it waits for it to pass.
+pub trait Clock {
+    fn now(&self) -> DateTime<Local>;
+
+    /// Waits while the lid moves, returning early only if the dispense has
+    /// to be aborted.
+    fn sleep(&self, duration: Duration) -> Result<(), Terminated>;
+
+    /// Waits for `duration` whatever happens, for waits that must not be cut
+    /// short, like a closing lid.
+    fn wait(&self, duration: Duration);
+
+    /// Waits until `deadline`, returning early with the first signal that
+    /// needs handling.
+    fn sleep_until(&self, deadline: DateTime<Local>) -> Option<Event>;
+}
+
+/// The wall clock, waiting in real time while handling signals.
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> DateTime<Local> {
+        Local::now()
+    }
+
+    fn sleep(&self, duration: Duration) -> Result<(), Terminated> {
+        signals::sleep(duration)
+    }
+
+    fn wait(&self, duration: Duration) {
+        thread::sleep(duration)
+    }
+
+    fn sleep_until(&self, deadline: DateTime<Local>) -> Option<Event> {
+        let remaining = (deadline - Local::now())
+            .to_std()
+            .unwrap_or_else(|_| Duration::from_millis(0));
+        signals::wait(remaining)
+    }
+}
+
+/// Adding to a `DateTime<Local>` keeps its UTC offset, this picks the
+/// offset that is in effect at the new time, across DST changes.
+fn in_local_time(time: DateTime<Local>) -> DateTime<Local> {
+    Local.from_utc_datetime(&time.naive_utc())
+}
+
+/// A clock that only moves when something sleeps on it, so the scheduler can
+/// run through days in milliseconds.
+pub struct VirtualClock {
+    now: Mutex<DateTime<Local>>,
+    speed: Option<f64>,
+}
+
+impl VirtualClock {
+    pub fn new(start: DateTime<Local>) -> VirtualClock {
+        VirtualClock {
+            now: Mutex::new(start),
+            speed: None,
+        }
+    }
+
+    /// Lets time pass `speed` times faster than real time instead of
+    /// jumping ahead right away. A termination signal ends the wait.
+    pub fn with_speed(start: DateTime<Local>, speed: f64) -> VirtualClock {
+        VirtualClock {
+            now: Mutex::new(start),
+            speed: Some(speed),
+        }
+    }
+
+    fn advance_to(&self, time: DateTime<Local>) -> Option<Event> {
+        let mut now = self.now.lock().unwrap();
+        if time <= *now {
+            return None;
+        }
+        let mut event = None;
+        if let (Some(speed), Ok(duration)) = (self.speed, (time - *now).to_std()) {
+            let deadline = Instant::now() + duration.div_f64(speed);
+            // nothing to reload or report here, only a shutdown matters
+            event = loop {
+                let remaining = deadline.saturating_duration_since(Instant::now());
+                match signals::wait(remaining) {
+                    None => break None,
+                    Some(Event::Shutdown) => break Some(Event::Shutdown),
+                    Some(_) => {}
+                }
+            };
+        }
+        *now = in_local_time(time);
+        event
+    }
+}
+
+impl Clock for VirtualClock {
+    fn now(&self) -> DateTime<Local> {
+        *self.now.lock().unwrap()
+    }
+
+    fn sleep(&self, duration: Duration) -> Result<(), Terminated> {
+        self.wait(duration);
+        Ok(())
+    }
+
+    fn wait(&self, duration: Duration) {
+        self.advance_to(self.now() + chrono::Duration::from_std(duration).unwrap());
+    }
+
+    fn sleep_until(&self, deadline: DateTime<Local>) -> Option<Event> {
+        self.advance_to(deadline)
+    }
+}
+
+#[test]
+fn virtual_clock_moves_only_when_sleeping() {
+    let start = Local.ymd(2019, 9, 1).and_hms(7, 30, 0);
+    let clock = VirtualClock::new(start);
+    assert_eq!(start, clock.now());
+
+    clock.sleep(Duration::from_millis(1500)).unwrap();
+    assert_eq!(start + chrono::Duration::milliseconds(1500), clock.now());
+
+    assert_eq!(None, clock.sleep_until(start));
+    assert_eq!(start + chrono::Duration::milliseconds(1500), clock.now());
+    clock.sleep_until(start + chrono::Duration::hours(1));
+    assert_eq!(Local.ymd(2019, 9, 1).and_hms(8, 30, 0), clock.now());
+}
@@ -0,0 +1,301 @@
+use chrono::prelude::*;
+use serde::{Deserialize, Serialize};
+use std::fs::File;
+use std::io::prelude::*;
+
+use crate::api::HttpConfig;
+use crate::control::ControlConfig;
+use crate::jiggle::JigglePattern;
+use crate::logging::LogConfig;
+use crate::motion::MotionProfile;
+use crate::watchdog::WatchdogConfig;
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum DailyRation {
+    Milliseconds(u64),
+    Grams { max: f64, grams_per_second: f64 },
+}
+
+impl DailyRation {
+    /// How long the lid may be open in a day. A ration in grams that can't
+    /// be converted allows no feeding at all rather than unlimited feeding.
+    pub fn max_ms(&self) -> u64 {
+        match *self {
+            DailyRation::Milliseconds(ms) => ms,
+            DailyRation::Grams { .. } if self.validate().is_err() => 0,
+            DailyRation::Grams {
+                max,
+                grams_per_second,
+            } => (max / grams_per_second * 1000.0) as u64,
+        }
+    }
+
+    pub fn validate(&self) -> Result<(), String> {
+        match *self {
+            DailyRation::Milliseconds(_) => Ok(()),
+            DailyRation::Grams {
+                max,
+                grams_per_second,
+            } => {
+                if !grams_per_second.is_finite() || grams_per_second <= 0.0 {
+                    Err(format!(
+                        "grams_per_second is {}, has to be more than 0",
+                        grams_per_second
+                    ))
+                } else if !max.is_finite() || max < 0.0 {
+                    Err(format!("max is {} grams, has to be 0 or more", max))
+                } else {
+                    Ok(())
+                }
+            }
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Default, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum OverBudget {
+    #[default]
+    Cap,
+    Refuse,
+}
+
+/// How often and for how long to try again when the lid failed to work.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+#[serde(default)]
+pub struct RetryPolicy {
+    /// Tries in total, the first one included.
+    pub attempts: u32,
+    pub delay_s: u64,
+    /// No try starts later than this after the first one.
+    pub give_up_after_s: u64,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        RetryPolicy {
+            attempts: 2,
+            delay_s: 60,
+            give_up_after_s: 600,
+        }
+    }
+}
+
+impl RetryPolicy {
+    /// When to try again after try number `attempt`, which began the feed at
+    /// `first_at`, failed at `now`. None when it is time to give up.
+    pub fn next_try(
+        &self,
+        attempt: u32,
+        first_at: DateTime<Local>,
+        now: DateTime<Local>,
+    ) -> Option<DateTime<Local>> {
+        if attempt >= self.attempts {
+            return None;
+        }
+        let next = now + chrono::Duration::seconds(self.delay_s as i64);
+        if next - first_at > chrono::Duration::seconds(self.give_up_after_s as i64) {
+            return None;
+        }
+        Some(next)
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct HopperConfig {
+    pub name: String,
+    /// The longest a single feed may keep the lid open.
+    #[serde(default = "default_max_open_ms")]
+    pub max_open_ms: u64,
+    #[serde(default)]
+    pub daily_max: Option<DailyRation>,
+    #[serde(default)]
+    pub over_budget: OverBudget,
+    #[serde(default)]
+    pub motion: MotionProfile,
+    #[serde(default)]
+    pub jiggle: JigglePattern,
+    #[serde(default)]
+    pub retry: RetryPolicy,
+}
+
+fn default_max_open_ms() -> u64 {
+    3000
+}
+
+impl HopperConfig {
+    pub fn unlimited(name: &str) -> HopperConfig {
+        HopperConfig {
+            name: String::from(name),
+            max_open_ms: default_max_open_ms(),
+            daily_max: None,
+            over_budget: OverBudget::default(),
+            motion: MotionProfile::default(),
+            jiggle: JigglePattern::default(),
+            retry: RetryPolicy::default(),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug)]
+pub struct BreakerConfig {
+    pub max_dispenses: u32,
+    pub window_minutes: u32,
+}
+
+impl Default for BreakerConfig {
+    fn default() -> Self {
+        BreakerConfig {
+            max_dispenses: 3,
+            window_minutes: 10,
+        }
+    }
+}
+
+/// What to do at startup about a feed that was interrupted by a crash or
+/// power loss.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Default, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum IncompleteFeed {
+    /// Count it as done, the lid may well have opened.
+    #[default]
+    AssumeFed,
+    /// Feed it once more if it was scheduled for today.
+    Refeed,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct Config {
+    pub hoppers: Vec<HopperConfig>,
+    #[serde(default)]
+    pub breaker: BreakerConfig,
+    #[serde(default)]
+    pub watchdog: Option<WatchdogConfig>,
+    #[serde(default)]
+    pub log: LogConfig,
+    #[serde(default)]
+    pub incomplete_feed: IncompleteFeed,
+    #[serde(default)]
+    pub control: ControlConfig,
+    /// The HTTP API is off unless this says where to serve it.
+    #[serde(default)]
+    pub http: Option<HttpConfig>,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            hoppers: vec![
+                HopperConfig {
+                    name: String::from("servo1"),
+                    max_open_ms: default_max_open_ms(),
+                    daily_max: Some(DailyRation::Milliseconds(5000)),
+                    over_budget: OverBudget::Cap,
+                    motion: MotionProfile::default(),
+                    jiggle: JigglePattern::default(),
+                    retry: RetryPolicy::default(),
+                },
+                HopperConfig {
+                    name: String::from("servo2"),
+                    max_open_ms: default_max_open_ms(),
+                    daily_max: Some(DailyRation::Milliseconds(4500)),
+                    over_budget: OverBudget::Cap,
+                    motion: MotionProfile::default(),
+                    jiggle: JigglePattern::default(),
+                    retry: RetryPolicy::default(),
+                },
+            ],
+            breaker: BreakerConfig::default(),
+            watchdog: None,
+            log: LogConfig::default(),
+            incomplete_feed: IncompleteFeed::default(),
+            control: ControlConfig::default(),
+            http: None,
+        }
+    }
+}
+
+impl Config {
+    /// Returns the configuration of the hopper at `index`, or an unlimited
+    /// one if the config file doesn't mention it.
+    pub fn hopper(&self, index: usize) -> HopperConfig {
+        match self.hoppers.get(index) {
+            Some(hopper) => hopper.clone(),
+            None => HopperConfig::unlimited(&format!("servo{}", index + 1)),
+        }
+    }
+}
+
+pub fn save(file_path: &str, config: &Config) -> Result<(), std::io::Error> {
+    crate::write_atomically(file_path, &serde_json::to_vec_pretty(config)?)
+}
+
+pub fn load(file_path: &str) -> Result<Config, std::io::Error> {
+    let mut file = File::open(file_path)?;
+    let mut file_content = String::new();
+    file.read_to_string(&mut file_content)?;
+    Ok(serde_json::from_str(&file_content)?)
+}
+
+#[test]
+fn grams_converted_to_ms() {
+    let ration = DailyRation::Grams {
+        max: 60.0,
+        grams_per_second: 20.0,
+    };
+
+    assert_eq!(3000, ration.max_ms());
+}
+
+#[test]
+fn unusable_grams_per_second_allows_no_feeding() {
+    for grams_per_second in [0.0, -20.0, f64::NAN, f64::INFINITY].iter() {
+        let ration = DailyRation::Grams {
+            max: 60.0,
+            grams_per_second: *grams_per_second,
+        };
+
+        assert!(ration.validate().is_err());
+        assert_eq!(0, ration.max_ms());
+    }
+}
+
+#[test]
+fn missing_hopper_is_unlimited() {
+    let config = Config {
+        hoppers: vec![],
+        breaker: BreakerConfig::default(),
+        watchdog: None,
+        log: LogConfig::default(),
+        incomplete_feed: IncompleteFeed::default(),
+        control: ControlConfig::default(),
+        http: None,
+    };
+
+    assert!(config.hopper(1).daily_max.is_none());
+    assert_eq!("servo2", config.hopper(1).name);
+}
+
+#[test]
+fn retries_until_attempts_or_window_run_out() {
+    let policy = RetryPolicy {
+        attempts: 3,
+        delay_s: 60,
+        give_up_after_s: 150,
+    };
+    let first = Local.ymd(2019, 9, 1).and_hms(7, 30, 0);
+
+    let second = policy.next_try(1, first, first + chrono::Duration::seconds(2));
+    assert_eq!(Some(Local.ymd(2019, 9, 1).and_hms(7, 31, 2)), second);
+    // a third try would start 182 s after the first
+    assert_eq!(
+        None,
+        policy.next_try(2, first, second.unwrap() + chrono::Duration::seconds(60))
+    );
+    assert_eq!(
+        Some(Local.ymd(2019, 9, 1).and_hms(7, 32, 4)),
+        policy.next_try(2, first, second.unwrap() + chrono::Duration::seconds(2))
+    );
+    assert_eq!(None, policy.next_try(3, first, first));
+}
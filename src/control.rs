@@ -0,0 +1,543 @@
+//! The daemon's control socket. Clients like `picatctl` connect to a Unix
+//! socket in the working directory and send one JSON request per line, each
+//! answered with one JSON response line. Who may connect is up to the mode of
+//! the socket file.
+
+use chrono::prelude::*;
+use log::{error, info, warn};
+use serde::{Deserialize, Serialize};
+use std::error::Error;
+use std::fs;
+use std::io::prelude::*;
+use std::io::BufReader;
+use std::net::Shutdown;
+use std::os::unix::fs::PermissionsExt;
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::sync::mpsc;
+use std::thread;
+
+use crate::actuation::Waiting;
+use crate::config::{self, Config};
+use crate::report::FeedReport;
+use crate::schedule::{Occasion, Schedule};
+use crate::{check, logging, persistant_schedule_storage, signals, state, Files};
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct ControlConfig {
+    /// Octal, like chmod takes it.
+    #[serde(default = "default_socket_mode")]
+    pub socket_mode: String,
+}
+
+fn default_socket_mode() -> String {
+    String::from("660")
+}
+
+impl Default for ControlConfig {
+    fn default() -> Self {
+        ControlConfig {
+            socket_mode: default_socket_mode(),
+        }
+    }
+}
+
+impl ControlConfig {
+    pub fn mode(&self) -> Result<u32, String> {
+        u32::from_str_radix(&self.socket_mode, 8)
+            .ok()
+            .filter(|mode| *mode <= 0o777)
+            .ok_or_else(|| format!("Invalid socket mode {}", self.socket_mode))
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[serde(tag = "command", rename_all = "snake_case")]
+pub enum Request {
+    Status,
+    /// Feeds right away, opening servo 1 for `ms` or as long as the next
+    /// scheduled feed would.
+    FeedNow {
+        #[serde(default)]
+        ms: Option<u64>,
+    },
+    SkipNext,
+    /// Lets feeding go on after the circuit breaker tripped.
+    ResetBreaker,
+    /// Lists the commands waiting for the hardware.
+    Queue,
+    /// Cancels a waiting command by the id `queue` lists it with.
+    Cancel {
+        id: u64,
+    },
+    Reload,
+    /// Stops scheduled feeding until resumed or restarted.
+    Pause,
+    Resume,
+    GetSchedule,
+    /// Replaces the schedule, given like `schedule.json` holds it.
+    SetSchedule {
+        schedule: serde_json::Value,
+    },
+    /// Adds an occasion, given like one in `schedule.json`.
+    AddOccasion {
+        occasion: serde_json::Value,
+    },
+    /// Replaces the occasion at the time of day `id`, like "07:30".
+    UpdateOccasion {
+        id: String,
+        occasion: serde_json::Value,
+    },
+    DeleteOccasion {
+        id: String,
+    },
+    GetConfig,
+    /// Replaces the config, given like `config.json` holds it.
+    SetConfig {
+        config: serde_json::Value,
+    },
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[serde(tag = "result", rename_all = "snake_case")]
+pub enum Response {
+    Done {
+        message: String,
+    },
+    Status {
+        status: String,
+        paused: bool,
+        skip_next: Option<DateTime<FixedOffset>>,
+    },
+    Fed {
+        report: FeedReport,
+    },
+    Queue {
+        commands: Vec<Waiting>,
+    },
+    Schedule {
+        schedule: serde_json::Value,
+    },
+    Config {
+        config: serde_json::Value,
+    },
+    NotFound {
+        message: String,
+    },
+    Error {
+        message: String,
+    },
+    /// Something went wrong in picat rather than with the request.
+    Failed {
+        message: String,
+    },
+}
+
+impl Response {
+    pub fn error(message: &str) -> Response {
+        Response::Error {
+            message: String::from(message),
+        }
+    }
+
+    pub fn failed(message: &str) -> Response {
+        Response::Failed {
+            message: String::from(message),
+        }
+    }
+
+    fn done(message: &str) -> Response {
+        Response::Done {
+            message: String::from(message),
+        }
+    }
+}
+
+fn parse_schedule(value: &serde_json::Value) -> Result<Schedule, String> {
+    let mut schedule = Schedule::new();
+    persistant_schedule_storage::deserialize(&value.to_string(), &mut schedule)?;
+    Ok(schedule)
+}
+
+fn parse_occasion(value: &serde_json::Value) -> Result<Occasion, String> {
+    let schedule = parse_schedule(&serde_json::Value::Array(vec![value.clone()]))?;
+    Ok(schedule.get_times()[0].clone())
+}
+
+/// Checks a schedule like `picat-check` does, then saves and uses it. Likely
+/// mistakes that were there before are passed on as warnings.
+fn replace_schedule(
+    files: &Files,
+    schedule: &mut Schedule,
+    changed: Schedule,
+    config: &Config,
+) -> Response {
+    let (refused, warnings) = check::schedule_change_problems(schedule, &changed, config);
+    if !refused.is_empty() {
+        return Response::error(&refused.join("; "));
+    }
+    if let Err(e) = persistant_schedule_storage::save(&files.schedule, &changed) {
+        return Response::failed(&format!("Failed to persist schedule: {}", e));
+    }
+    info!(occasions = changed.get_times().len(); "Schedule replaced");
+    *schedule = changed;
+    if warnings.is_empty() {
+        return Response::done("Schedule saved");
+    }
+    let warnings = warnings.join("; ");
+    warn!(warnings:% = warnings; "Saved schedule has likely mistakes");
+    Response::done(&format!("Schedule saved, but {}", warnings))
+}
+
+/// Checks a config, also against the schedule it will feed, then saves and
+/// uses it.
+fn replace_config(
+    files: &Files,
+    schedule: &Schedule,
+    config: &mut Config,
+    value: &serde_json::Value,
+) -> Response {
+    let changed: Config = match serde_json::from_value(value.clone()) {
+        Ok(changed) => changed,
+        Err(e) => return Response::error(&format!("Invalid config: {}", e)),
+    };
+    let problems = check::settings_problems(schedule, &changed);
+    if !problems.is_empty() {
+        return Response::error(&problems.join("; "));
+    }
+    if let Err(e) = config::save(&files.config, &changed) {
+        return Response::failed(&format!("Failed to persist config: {}", e));
+    }
+    if let Err(e) = logging::configure(&changed.log) {
+        error!(error:% = e; "Failed to configure logging");
+    }
+    info!("Config replaced");
+    *config = changed;
+    Response::done("Config saved")
+}
+
+/// Answers the requests that read or change the schedule and config, with
+/// the checks and storage the command line tools use. Changes are saved
+/// before they are used.
+pub fn answer_settings(
+    request: &Request,
+    files: &Files,
+    schedule: &mut Schedule,
+    config: &mut Config,
+) -> Response {
+    let not_found = |id: &str| Response::NotFound {
+        message: format!("No occasion at {}", id),
+    };
+    match *request {
+        Request::GetSchedule => {
+            match serde_json::from_str(&persistant_schedule_storage::serialize(schedule)) {
+                Ok(schedule) => Response::Schedule { schedule },
+                Err(e) => Response::failed(&e.to_string()),
+            }
+        }
+        Request::SetSchedule {
+            schedule: ref value,
+        } => match parse_schedule(value) {
+            Ok(changed) => replace_schedule(files, schedule, changed, config),
+            Err(e) => Response::error(&e),
+        },
+        Request::AddOccasion { ref occasion } => match parse_occasion(occasion) {
+            Ok(occasion) => {
+                let mut changed = schedule.clone();
+                changed.push(occasion);
+                replace_schedule(files, schedule, changed, config)
+            }
+            Err(e) => Response::error(&e),
+        },
+        Request::UpdateOccasion {
+            ref id,
+            ref occasion,
+        } => match parse_occasion(occasion) {
+            Ok(occasion) => {
+                let mut changed = schedule.clone();
+                if !changed.replace(id, occasion) {
+                    return not_found(id);
+                }
+                replace_schedule(files, schedule, changed, config)
+            }
+            Err(e) => Response::error(&e),
+        },
+        Request::DeleteOccasion { ref id } => {
+            let mut changed = schedule.clone();
+            if !changed.remove(id) {
+                return not_found(id);
+            }
+            replace_schedule(files, schedule, changed, config)
+        }
+        Request::GetConfig => match serde_json::to_value(&*config) {
+            Ok(config) => Response::Config { config },
+            Err(e) => Response::failed(&e.to_string()),
+        },
+        Request::SetConfig { config: ref value } => replace_config(files, schedule, config, value),
+        _ => Response::failed("Not a schedule or config request"),
+    }
+}
+
+/// Resets the circuit breaker, for the daemon or for `picat reset-breaker`
+/// when no daemon runs.
+pub fn reset_breaker(files: &Files) -> Response {
+    match state::reset_breaker(&files.state) {
+        Ok(Some(t)) => {
+            info!("Circuit breaker tripped at {} reset", t);
+            Response::done(&format!("Circuit breaker tripped at {} reset", t))
+        }
+        Ok(None) => Response::done("Circuit breaker was not tripped"),
+        Err(e) => Response::failed(&format!("Failed to persist state: {}", e)),
+    }
+}
+
+/// What clients asked of scheduled feeding, kept until the daemon restarts.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Overrides {
+    pub paused: bool,
+    /// When the scheduled feed to leave out is due.
+    pub skip_next: Option<DateTime<Local>>,
+}
+
+impl Overrides {
+    /// Leaves out the next scheduled feed after `now` and returns when it is
+    /// due. Asking again before it came due still skips just that one.
+    pub fn skip_next(
+        &mut self,
+        schedule: &Schedule,
+        now: DateTime<Local>,
+    ) -> Option<DateTime<Local>> {
+        self.skip_next = schedule.next_after(now);
+        self.skip_next
+    }
+
+    /// Why the occasion that came due at `now` is not to be fed, if it isn't.
+    pub fn hold(&mut self, occasion: &Occasion, now: DateTime<Local>) -> Option<&'static str> {
+        let skipped = match self.skip_next {
+            Some(due) => {
+                due.date() == now.date() && due.format("%H:%M").to_string() == occasion.id()
+            }
+            None => false,
+        };
+        if skipped {
+            self.skip_next = None;
+        }
+        if self.paused {
+            Some("paused")
+        } else if skipped {
+            Some("skipped on request")
+        } else {
+            None
+        }
+    }
+}
+
+/// A request from a client, waiting for the feeder loop to answer it.
+pub struct Call {
+    pub request: Request,
+    reply: mpsc::Sender<Response>,
+}
+
+impl Call {
+    pub fn answer(self, response: Response) {
+        // the client may have hung up meanwhile
+        let _ = self.reply.send(response);
+    }
+}
+
+/// Listens on the socket at `file_path` in the background and passes each
+/// request on to `calls`, waking the feeder loop to answer it.
+pub fn listen(file_path: &str, mode: u32, calls: mpsc::Sender<Call>) -> Result<(), Box<dyn Error>> {
+    // only the holder of the hardware lock listens, so a socket left behind is stale
+    match fs::remove_file(file_path) {
+        Ok(()) => {}
+        Err(ref e) if e.kind() == std::io::ErrorKind::NotFound => {}
+        Err(e) => return Err(e.into()),
+    }
+    let listener = UnixListener::bind(file_path)?;
+    fs::set_permissions(file_path, fs::Permissions::from_mode(mode))?;
+    thread::spawn(move || {
+        for stream in listener.incoming() {
+            match stream {
+                Ok(stream) => {
+                    let calls = calls.clone();
+                    thread::spawn(move || {
+                        if let Err(e) = serve(stream, &calls) {
+                            warn!(error:% = e; "Control connection failed");
+                        }
+                    });
+                }
+                Err(e) => error!(error:% = e; "Failed to accept control connection"),
+            }
+        }
+    });
+    Ok(())
+}
+
+fn serve(stream: UnixStream, calls: &mpsc::Sender<Call>) -> Result<(), Box<dyn Error>> {
+    let mut writer = stream.try_clone()?;
+    for line in BufReader::new(stream).lines() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        let response = match serde_json::from_str(&line) {
+            Ok(request) => call(calls, request),
+            Err(e) => Response::error(&format!("Invalid request: {}", e)),
+        };
+        writeln!(writer, "{}", serde_json::to_string(&response)?)?;
+    }
+    Ok(())
+}
+
+/// Passes a request on to the feeder loop and waits for the answer.
+pub fn call(calls: &mpsc::Sender<Call>, request: Request) -> Response {
+    let (sender, receiver) = mpsc::channel();
+    let call = Call {
+        request,
+        reply: sender,
+    };
+    if calls.send(call).is_err() {
+        return Response::failed("picat is shutting down");
+    }
+    signals::wake();
+    receiver
+        .recv()
+        .unwrap_or_else(|_| Response::failed("picat is shutting down"))
+}
+
+/// Sends one request to the daemon listening at `file_path` and waits for
+/// the answer.
+pub fn send(file_path: &str, request: &Request) -> Result<Response, Box<dyn Error>> {
+    let mut stream = UnixStream::connect(file_path)?;
+    writeln!(stream, "{}", serde_json::to_string(request)?)?;
+    stream.shutdown(Shutdown::Write)?;
+    let mut line = String::new();
+    BufReader::new(stream).read_line(&mut line)?;
+    Ok(serde_json::from_str(&line)?)
+}
+
+#[cfg(test)]
+fn breakfast() -> Schedule {
+    let mut schedule = Schedule::new();
+    schedule.push(Occasion {
+        time: Local.ymd(1970, 1, 1).and_hms(7, 30, 0),
+        enabled_weekdays: vec![Weekday::Mon, Weekday::Tue],
+        opened_time_servo1: 300,
+        opened_time_servo2: 300,
+        jiggle_servo1: None,
+        jiggle_servo2: None,
+    });
+    schedule
+}
+
+#[test]
+fn skip_next_holds_back_one_feed() {
+    let schedule = breakfast();
+    let occasion = &schedule.get_times()[0];
+    let mut overrides = Overrides::default();
+
+    let due = overrides.skip_next(&schedule, Local.ymd(2019, 9, 2).and_hms(5, 0, 0));
+    assert_eq!(Some(Local.ymd(2019, 9, 2).and_hms(7, 30, 0)), due);
+    assert_eq!(
+        Some("skipped on request"),
+        overrides.hold(occasion, Local.ymd(2019, 9, 2).and_hms(7, 30, 0))
+    );
+    assert_eq!(
+        None,
+        overrides.hold(occasion, Local.ymd(2019, 9, 3).and_hms(7, 30, 0))
+    );
+}
+
+#[test]
+fn pause_holds_back_every_feed() {
+    let schedule = breakfast();
+    let occasion = &schedule.get_times()[0];
+    let mut overrides = Overrides {
+        paused: true,
+        skip_next: None,
+    };
+
+    assert_eq!(
+        Some("paused"),
+        overrides.hold(occasion, Local.ymd(2019, 9, 2).and_hms(7, 30, 0))
+    );
+    assert_eq!(
+        Some("paused"),
+        overrides.hold(occasion, Local.ymd(2019, 9, 3).and_hms(7, 30, 0))
+    );
+}
+
+#[test]
+fn only_mistakes_a_change_brings_in_refuse_it() {
+    let dir = std::env::temp_dir().join(format!("picat-control-lint-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let files = Files::in_dir(&dir);
+    let mut schedule = Schedule::new();
+    crate::scheduler::create_default_schedule(&mut schedule);
+    let mut config = Config::default();
+    let occasion = |hour: u32, minute: u32| {
+        serde_json::json!({
+            "enabled": true,
+            "time": Local.ymd(1970, 1, 1).and_hms(hour, minute, 0).to_rfc3339(),
+            "enabled_weekdays": [1],
+            "opened_time_servo1": 300,
+            "opened_time_servo2": 300,
+        })
+    };
+    let add = |hour: u32, minute: u32| Request::AddOccasion {
+        occasion: occasion(hour, minute),
+    };
+
+    let response = answer_settings(&add(9, 0), &files, &mut schedule, &mut config);
+    assert_eq!(
+        Response::done("Schedule saved, but 11:30 is scheduled 3 times"),
+        response
+    );
+    let response = answer_settings(&add(11, 30), &files, &mut schedule, &mut config);
+    assert_eq!(Response::error("11:30 is scheduled 4 times"), response);
+    let update = Request::UpdateOccasion {
+        id: String::from("11:30"),
+        occasion: occasion(11, 30),
+    };
+    let response = answer_settings(&update, &files, &mut schedule, &mut config);
+    assert_eq!(
+        Response::done("Schedule saved, but 11:30 is scheduled 3 times"),
+        response
+    );
+    let response = answer_settings(
+        &Request::DeleteOccasion {
+            id: String::from("11:30"),
+        },
+        &files,
+        &mut schedule,
+        &mut config,
+    );
+    assert_eq!(Response::done("Schedule saved"), response);
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn config_is_refused_when_the_schedule_opens_longer_than_it_allows() {
+    let dir = std::env::temp_dir().join(format!("picat-control-max-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let files = Files::in_dir(&dir);
+    let mut schedule = breakfast();
+    let mut config = Config::default();
+    let mut lowered = serde_json::to_value(&config).unwrap();
+    lowered["hoppers"][0]["max_open_ms"] = serde_json::json!(200);
+
+    let response = answer_settings(
+        &Request::SetConfig { config: lowered },
+        &files,
+        &mut schedule,
+        &mut config,
+    );
+
+    assert_eq!(
+        Response::error("07:30 opens servo1 for 300 ms, longer than its max_open_ms of 200"),
+        response
+    );
+    assert_eq!(3000, config.hopper(0).max_open_ms);
+    assert!(!std::path::Path::new(&files.config).exists());
+    std::fs::remove_dir_all(&dir).unwrap();
+}
@@ -0,0 +1,105 @@
+use rppal::pwm::Channel;
+use std::cell::Cell;
+use std::error::Error;
+use std::fmt;
+use std::time::Duration;
+
+use crate::clock::Clock;
+use crate::hardware::Hardware;
+use crate::servo::{Actuator, PulseLimits};
+use crate::trace::{Trace, TracingActuator};
+
+/// What the faulty hardware gets wrong. Calls are counted over every actuator
+/// it hands out, so a fault hits one feed and not each one.
+#[derive(Clone, Debug, Default)]
+pub struct FaultPlan {
+    /// Fails the Nth pulse command, counting from 1.
+    pub fail_pulse: Option<usize>,
+    /// Fails creating this many actuators before creating one works.
+    pub fail_creates: usize,
+    /// Fails every disable.
+    pub fail_disable: bool,
+    /// Stalls the Nth pulse command for a while before carrying it out.
+    pub hang_pulse: Option<(usize, Duration)>,
+}
+
+#[derive(Debug)]
+pub struct InjectedFault(pub &'static str);
+
+impl fmt::Display for InjectedFault {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Injected fault: {}", self.0)
+    }
+}
+
+impl Error for InjectedFault {}
+
+/// Hardware that records into a trace like `TracingHardware`, but fails or
+/// stalls as planned, for testing the error paths.
+pub struct FaultyHardware<'a> {
+    trace: &'a Trace,
+    clock: &'a dyn Clock,
+    plan: FaultPlan,
+    creates: Cell<usize>,
+    pulses: Cell<usize>,
+}
+
+impl<'a> FaultyHardware<'a> {
+    pub fn new(trace: &'a Trace, clock: &'a dyn Clock, plan: FaultPlan) -> FaultyHardware<'a> {
+        FaultyHardware {
+            trace,
+            clock,
+            plan,
+            creates: Cell::new(0),
+            pulses: Cell::new(0),
+        }
+    }
+}
+
+impl<'a> Hardware for FaultyHardware<'a> {
+    fn actuator(
+        &self,
+        channel: Channel,
+        pulse_closed: u64,
+        limits: PulseLimits,
+    ) -> Result<Box<dyn Actuator + '_>, Box<dyn Error>> {
+        self.creates.set(self.creates.get() + 1);
+        if self.creates.get() <= self.plan.fail_creates {
+            return Err(Box::new(InjectedFault("PWM unavailable")));
+        }
+        limits.check(pulse_closed)?;
+        let actuator = FaultyActuator {
+            hardware: self,
+            inner: TracingActuator::new(self.trace, self.clock, channel),
+        };
+        actuator.set_pulse_width(Duration::from_micros(pulse_closed))?;
+        Ok(Box::new(actuator))
+    }
+}
+
+struct FaultyActuator<'h, 'a> {
+    hardware: &'h FaultyHardware<'a>,
+    inner: TracingActuator<'a>,
+}
+
+impl<'h, 'a> Actuator for FaultyActuator<'h, 'a> {
+    fn set_pulse_width(&self, pulse_width: Duration) -> Result<(), Box<dyn Error>> {
+        let hardware = self.hardware;
+        hardware.pulses.set(hardware.pulses.get() + 1);
+        let call = hardware.pulses.get();
+        if hardware.plan.fail_pulse == Some(call) {
+            return Err(Box::new(InjectedFault("pulse command failed")));
+        }
+        if let Some((_, duration)) = hardware.plan.hang_pulse.filter(|&(n, _)| n == call) {
+            hardware.clock.wait(duration);
+        }
+        self.inner.set_pulse_width(pulse_width)
+    }
+
+    fn disable(&self) -> Result<(), Box<dyn Error>> {
+        if self.hardware.plan.fail_disable {
+            return Err(Box::new(InjectedFault("disable failed")));
+        }
+        self.inner.disable()
+    }
+}
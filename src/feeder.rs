@@ -0,0 +1,579 @@
+use chrono::prelude::*;
+use log::{error, info, warn};
+use rppal::pwm::Channel;
+use std::error::Error;
+use std::time::Duration;
+
+use crate::backoff::Backoff;
+use crate::clock::Clock;
+use crate::hardware::{
+    probe_hardware, Hardware, HARDWARE_RETRY_MAX_S, HARDWARE_RETRY_MIN_S, PULSE_CLOSED_US,
+    PULSE_OPEN_US, PULSE_PASSED_US, SAFE_STATE_TRAVEL_MS, SERVO1_LIMITS,
+};
+use crate::report::{FeedError, FeedReport};
+use crate::{
+    alert, breaker, config, history, jiggle, ration, safe_state, schedule, servo, state, status,
+    Files,
+};
+
+/// A request to feed from one hopper.
+pub struct FeedRequest {
+    pub trigger: history::Trigger,
+    pub occasion: String,
+    pub requested_ms: u64,
+    pub jiggle: jiggle::JigglePattern,
+    pub attempt: u32,
+}
+
+/// One try at feeding an occasion.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Attempt {
+    pub number: u32,
+    /// When the first try began.
+    pub first_at: DateTime<Local>,
+}
+
+impl Attempt {
+    pub fn first(now: DateTime<Local>) -> Attempt {
+        Attempt {
+            number: 1,
+            first_at: now,
+        }
+    }
+
+    pub fn next(self) -> Attempt {
+        Attempt {
+            number: self.number + 1,
+            ..self
+        }
+    }
+}
+
+fn shake(
+    servo: &servo::Servo,
+    shake: &jiggle::Shake,
+    clock: &dyn Clock,
+    cycles: &mut u32,
+) -> Result<(), Box<dyn Error>> {
+    for _ in 0..shake.repetitions {
+        for step in shake.steps.iter() {
+            servo.move_to(step.position.pulse(servo))?;
+            clock.sleep(Duration::from_millis(step.dwell_ms))?;
+        }
+        *cycles += 1;
+    }
+    Ok(())
+}
+
+fn move_lid(
+    servo: &servo::Servo,
+    feed_time: u64,
+    jiggle: &jiggle::JigglePattern,
+    clock: &dyn Clock,
+    cycles: &mut u32,
+) -> Result<(), Box<dyn Error>> {
+    if let Some(ref pre_open) = jiggle.pre_open {
+        shake(servo, pre_open, clock, cycles)?;
+    }
+    servo.move_to(servo.pulse_open)?;
+    clock.sleep(Duration::from_millis(feed_time))?;
+
+    if let Some(ref after_open) = jiggle.after_open {
+        shake(servo, after_open, clock, cycles)?;
+    }
+    if servo.position() != servo.pulse_closed {
+        servo.move_to(servo.pulse_closed)?;
+    }
+    Ok(())
+}
+
+/// Opens the lid of `hopper` for `open_ms`, jiggling it as asked, and closes
+/// it again. The report says how it went.
+pub fn feed_cat(
+    servo: &servo::Servo,
+    hopper: &str,
+    requested_ms: u64,
+    open_ms: u64,
+    jiggle: &jiggle::JigglePattern,
+    clock: &dyn Clock,
+) -> FeedReport {
+    let started = clock.now();
+    let mut report = FeedReport {
+        hopper: String::from(hopper),
+        requested_ms,
+        open_ms,
+        elapsed_ms: 0,
+        jiggle_cycles: 0,
+        sensor_readings: Vec::new(),
+        error: None,
+    };
+    match servo.pwm {
+        Some(_) => {
+            if let Err(e) = move_lid(servo, open_ms, jiggle, clock, &mut report.jiggle_cycles) {
+                report.error = Some(FeedError::from_motion(e));
+            }
+        }
+        None => {
+            report.error = Some(FeedError::HardwareUnavailable {
+                reason: format!("no PWM for {}", hopper),
+            });
+        }
+    }
+    report.elapsed_ms = (clock.now() - started).num_milliseconds() as u64;
+    report
+}
+
+/// Checks the requested open time against the hopper's daily ration and
+/// returns how long the lid may actually be opened, if at all.
+pub fn authorize_feed(
+    rations: &mut ration::RationTracker,
+    hopper: &config::HopperConfig,
+    requested_ms: u64,
+    today: NaiveDate,
+) -> Option<u64> {
+    rations.roll_over(today);
+    match rations.allowance(hopper, requested_ms) {
+        ration::Allowance::Full(ms) => Some(ms),
+        ration::Allowance::Capped(ms) => {
+            alert::raise(&format!(
+                "Daily ration for {} almost used up, capping feed from {} ms to {} ms",
+                hopper.name, requested_ms, ms
+            ));
+            Some(ms)
+        }
+        ration::Allowance::Refused => {
+            alert::raise(&format!(
+                "Daily ration for {} used up, refusing feed of {} ms",
+                hopper.name, requested_ms
+            ));
+            None
+        }
+    }
+}
+
+pub fn breaker_allows(
+    breaker_config: &config::BreakerConfig,
+    files: &Files,
+    now: DateTime<Local>,
+) -> bool {
+    let decision = state::update(&files.state, |state| {
+        state.breaker.allow_dispense(now, breaker_config)
+    });
+
+    match decision {
+        breaker::Decision::Allowed => true,
+        breaker::Decision::JustTripped => {
+            alert::raise(&format!(
+                "Circuit breaker tripped after {} dispenses within {} minutes, \
+                 no feeding until reset with `picat reset-breaker`",
+                breaker_config.max_dispenses, breaker_config.window_minutes
+            ));
+            false
+        }
+        breaker::Decision::Tripped => {
+            warn!("Circuit breaker is tripped, not feeding");
+            false
+        }
+    }
+}
+
+/// Picks the occasion's jiggle pattern over the hopper's, falling back to the
+/// default pattern if the chosen one doesn't fit the servo.
+pub fn jiggle_for(
+    servo: &servo::Servo,
+    hopper: &config::HopperConfig,
+    occasion_jiggle: Option<&jiggle::JigglePattern>,
+) -> jiggle::JigglePattern {
+    let jiggle = occasion_jiggle.unwrap_or(&hopper.jiggle);
+    match jiggle.validate(servo) {
+        Ok(_) => jiggle.clone(),
+        Err(e) => {
+            warn!(
+                hopper = hopper.name.as_str(), error = e.as_str();
+                "Invalid jiggle pattern, using default"
+            );
+            jiggle::JigglePattern::default()
+        }
+    }
+}
+
+/// Adds a feed attempt to the feeding history. There is no report when a
+/// guard refused the feed before the lid could move.
+fn record_history(
+    files: &Files,
+    time: DateTime<Local>,
+    request: &FeedRequest,
+    hopper: &str,
+    outcome: history::Outcome,
+    report: Option<&FeedReport>,
+) {
+    let entry = history::Entry {
+        time: time.into(),
+        trigger: request.trigger,
+        occasion: request.occasion.clone(),
+        hopper: String::from(hopper),
+        requested_ms: request.requested_ms,
+        open_ms: report.map_or(0, |r| r.open_ms),
+        outcome,
+        error: report.and_then(|r| r.error.as_ref()).map(|e| e.to_string()),
+        attempt: request.attempt,
+        elapsed_ms: report.map_or(0, |r| r.elapsed_ms),
+        jiggle_cycles: report.map_or(0, |r| r.jiggle_cycles),
+    };
+    if let Err(e) = history::append(&files.history, &entry) {
+        error!(error:% = e; "Failed to append to feeding history");
+    }
+}
+
+/// Feeds the cat unless the daily ration or the circuit breaker says no,
+/// never for longer than the hopper's `max_open_ms`. Returns the report of `feed_cat` if it was run. Every attempt ends up in
+/// the feeding history.
+pub fn guarded_feed(
+    servo: &servo::Servo,
+    hopper: &config::HopperConfig,
+    request: &FeedRequest,
+    config: &config::Config,
+    files: &Files,
+    clock: &dyn Clock,
+) -> Option<FeedReport> {
+    let occasion = request.occasion.as_str();
+    let requested_ms = request.requested_ms;
+    let time = clock.now().with_nanosecond(0).unwrap();
+
+    // nothing can move, so neither the ration nor the breaker has a say
+    if servo.pwm.is_none() {
+        let report = feed_cat(servo, &hopper.name, requested_ms, 0, &request.jiggle, clock);
+        error!(
+            occasion = occasion, hopper = hopper.name.as_str(), outcome = "failed",
+            error:% = report.error.as_ref().unwrap();
+            "Failed to feed the cat"
+        );
+        let outcome = history::Outcome::Failed;
+        record_history(files, time, request, &hopper.name, outcome, Some(&report));
+        return Some(report);
+    }
+
+    // every feed passes here, whatever file or request it came from
+    let allowed_ms = if requested_ms > hopper.max_open_ms {
+        alert::raise(&format!(
+            "{} asks to open {} for {} ms, capping at its max_open_ms of {}",
+            occasion, hopper.name, requested_ms, hopper.max_open_ms
+        ));
+        hopper.max_open_ms
+    } else {
+        requested_ms
+    };
+    let authorized = state::update(&files.state, |state| {
+        authorize_feed(
+            &mut state.rations,
+            hopper,
+            allowed_ms,
+            time.date().naive_local(),
+        )
+    });
+    let open_ms = match authorized {
+        Some(ms) => ms,
+        None => {
+            let outcome = history::Outcome::RationRefused;
+            record_history(files, time, request, &hopper.name, outcome, None);
+            return None;
+        }
+    };
+    if !breaker_allows(&config.breaker, files, time) {
+        let outcome = history::Outcome::BreakerTripped;
+        record_history(files, time, request, &hopper.name, outcome, None);
+        return None;
+    }
+
+    // counted even on failure, the lid may have opened before the error
+    let report = feed_cat(
+        servo,
+        &hopper.name,
+        requested_ms,
+        open_ms,
+        &request.jiggle,
+        clock,
+    );
+    state::update(&files.state, |state| {
+        state.rations.record(&hopper.name, open_ms);
+        state.fired(occasion, time, &report.outcome());
+    });
+    match report.error {
+        None => {
+            info!(
+                occasion = occasion, hopper = hopper.name.as_str(), requested_ms = requested_ms,
+                open_ms = open_ms, elapsed_ms = report.elapsed_ms, outcome = "fed";
+                "Fed the cat"
+            );
+            let outcome = history::Outcome::Fed;
+            record_history(files, time, request, &hopper.name, outcome, Some(&report));
+        }
+        Some(ref e) => {
+            error!(
+                occasion = occasion, hopper = hopper.name.as_str(), requested_ms = requested_ms,
+                open_ms = open_ms, elapsed_ms = report.elapsed_ms, outcome = "failed", error:% = e;
+                "Failed to feed the cat"
+            );
+            let outcome = history::Outcome::Failed;
+            record_history(files, time, request, &hopper.name, outcome, Some(&report));
+        }
+    }
+    Some(report)
+}
+
+/// Shows whether the PWM could be set up, in the status and the state file.
+/// Raises an alert when feeding becomes degraded.
+pub fn note_hardware(files: &Files, status: &mut status::Status, fault: Option<String>) {
+    match (&status.fault, &fault) {
+        (None, Some(e)) => alert::raise(&format!(
+            "PWM unavailable, feeds fail until it comes back: {}",
+            e
+        )),
+        (Some(_), None) => info!("PWM available again, leaving degraded mode"),
+        _ => {}
+    }
+    status.hardware = match fault {
+        Some(ref e) => format!("degraded, PWM unavailable: {}", e),
+        None => String::from("ok"),
+    };
+    status.fault = fault.clone();
+    state::update(&files.state, |state| state.faults.hardware = fault);
+}
+
+/// Brings the PWM back after it failed, probing it with growing pauses while
+/// feeding is degraded.
+pub struct HardwareRetry {
+    backoff: Backoff,
+    next_probe: Option<DateTime<Local>>,
+}
+
+impl Default for HardwareRetry {
+    fn default() -> Self {
+        HardwareRetry::new()
+    }
+}
+
+impl HardwareRetry {
+    pub fn new() -> HardwareRetry {
+        HardwareRetry {
+            backoff: Backoff::new(
+                Duration::from_secs(HARDWARE_RETRY_MIN_S),
+                Duration::from_secs(HARDWARE_RETRY_MAX_S),
+            ),
+            next_probe: None,
+        }
+    }
+
+    /// When to probe the hardware next, if feeding is degraded.
+    pub fn next_probe(
+        &mut self,
+        status: &status::Status,
+        now: DateTime<Local>,
+    ) -> Option<DateTime<Local>> {
+        if status.fault.is_none() {
+            self.backoff.reset();
+            self.next_probe = None;
+            return None;
+        }
+        let backoff = &mut self.backoff;
+        Some(
+            *self.next_probe.get_or_insert_with(|| {
+                now + chrono::Duration::from_std(backoff.next_delay()).unwrap()
+            }),
+        )
+    }
+
+    /// Probes the hardware if feeding is degraded and it is time to.
+    pub fn probe_if_due(
+        &mut self,
+        files: &Files,
+        status: &mut status::Status,
+        hardware: &dyn Hardware,
+        now: DateTime<Local>,
+    ) {
+        match self.next_probe(status, now) {
+            Some(due) if now >= due => {
+                self.next_probe = None;
+                note_hardware(files, status, probe_hardware(hardware));
+                if status.fault.is_some() {
+                    warn!(hardware = status.hardware.as_str(); "PWM still unavailable");
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+/// Records a feed that could not happen because the PWM is unavailable.
+fn fail_degraded(
+    request: &FeedRequest,
+    hopper: &config::HopperConfig,
+    files: &Files,
+    status: &mut status::Status,
+    clock: &dyn Clock,
+    fault: &str,
+) -> FeedReport {
+    let time = clock.now().with_nanosecond(0).unwrap();
+    let report = FeedReport {
+        hopper: hopper.name.clone(),
+        requested_ms: request.requested_ms,
+        open_ms: 0,
+        elapsed_ms: 0,
+        jiggle_cycles: 0,
+        sensor_readings: Vec::new(),
+        error: Some(FeedError::HardwareUnavailable {
+            reason: String::from(fault),
+        }),
+    };
+    alert::raise(&format!(
+        "Could not feed {}, {}",
+        request.occasion,
+        report.error.as_ref().unwrap()
+    ));
+    let outcome = history::Outcome::Failed;
+    record_history(files, time, request, &hopper.name, outcome, Some(&report));
+    state::update(&files.state, |state| {
+        state.fired(&request.occasion, time, &report.outcome())
+    });
+    status.last_feed = Some(time);
+    status.last_outcome = Some(report.outcome());
+    report
+}
+
+/// How feeding an occasion went.
+#[derive(Clone, Debug, PartialEq)]
+pub struct FeedOutcome {
+    /// Missing when a guard refused the feed.
+    pub report: Option<FeedReport>,
+    /// When to try again, if the lid failed to work and the hopper's retry
+    /// policy allows another try.
+    pub retry_at: Option<DateTime<Local>>,
+}
+
+/// What feeding an occasion works with: the config, the files to keep track
+/// of it in, the time and the servos.
+#[derive(Clone, Copy)]
+pub struct Feeder<'a> {
+    pub config: &'a config::Config,
+    pub files: &'a Files,
+    pub clock: &'a dyn Clock,
+    pub hardware: &'a dyn Hardware,
+}
+
+impl<'a> Feeder<'a> {
+    /// Feeds an occasion from the schedule and updates the status with how
+    /// it went.
+    pub fn feed_occasion(
+        &self,
+        occasion: &schedule::Occasion,
+        trigger: history::Trigger,
+        attempt: Attempt,
+        status: &mut status::Status,
+    ) -> FeedOutcome {
+        let (config, files, clock) = (self.config, self.files, self.clock);
+        let hopper = config.hopper(0);
+        let retry = |clock: &dyn Clock| {
+            hopper
+                .retry
+                .next_try(attempt.number, attempt.first_at, clock.now())
+        };
+        let pwm = match self
+            .hardware
+            .actuator(Channel::Pwm0, PULSE_CLOSED_US, SERVO1_LIMITS)
+        {
+            Ok(pwm) => pwm,
+            Err(e) => {
+                error!(error:% = e; "Failed to create servo1, not feeding");
+                note_hardware(files, status, Some(e.to_string()));
+                let request = FeedRequest {
+                    trigger,
+                    occasion: occasion.id(),
+                    requested_ms: occasion.opened_time_servo1,
+                    jiggle: hopper.jiggle.clone(),
+                    attempt: attempt.number,
+                };
+                let report = fail_degraded(&request, &hopper, files, status, clock, &e.to_string());
+                return FeedOutcome {
+                    report: Some(report),
+                    retry_at: retry(clock),
+                };
+            }
+        };
+        note_hardware(files, status, None);
+
+        let servo1 = servo::Servo::new(
+            SERVO1_LIMITS,
+            PULSE_CLOSED_US,
+            PULSE_OPEN_US,
+            PULSE_PASSED_US,
+            hopper.motion,
+            Some(pwm.as_ref()),
+        )
+        .with_clock(clock);
+        let safe_state =
+            safe_state::SafeState::new(vec![&servo1], Duration::from_millis(SAFE_STATE_TRAVEL_MS));
+        let request = FeedRequest {
+            trigger,
+            occasion: occasion.id(),
+            requested_ms: occasion.opened_time_servo1,
+            jiggle: jiggle_for(&servo1, &hopper, occasion.jiggle_servo1.as_ref()),
+            attempt: attempt.number,
+        };
+        // 2150 tot
+        let report = guarded_feed(&servo1, &hopper, &request, config, files, clock);
+        safe_state.engage();
+        let retry_at = match report {
+            Some(ref report) => {
+                status.last_feed = Some(clock.now());
+                status.last_outcome = Some(report.outcome());
+                if report.is_fed() {
+                    None
+                } else {
+                    retry(clock)
+                }
+            }
+            None => None,
+        };
+        FeedOutcome { report, retry_at }
+    }
+}
+
+/// Leaves an occasion that came due unfed, recording why in the log and as
+/// skipped in the feeding history.
+pub fn skip_occasion(
+    occasion: &schedule::Occasion,
+    trigger: history::Trigger,
+    attempt: Attempt,
+    config: &config::Config,
+    files: &Files,
+    clock: &dyn Clock,
+    reason: &str,
+) {
+    let hopper = config.hopper(0);
+    info!(occasion = occasion.id().as_str(), reason = reason; "Not feeding");
+    let request = FeedRequest {
+        trigger,
+        occasion: occasion.id(),
+        requested_ms: occasion.opened_time_servo1,
+        jiggle: hopper.jiggle.clone(),
+        attempt: attempt.number,
+    };
+    let time = clock.now().with_nanosecond(0).unwrap();
+    let outcome = history::Outcome::Skipped;
+    record_history(files, time, &request, &hopper.name, outcome, None);
+}
+
+/// Closes the lid and disables the PWM, whatever state it was left in.
+pub fn close_lids(hardware: &dyn Hardware, clock: &dyn Clock) -> Result<(), Box<dyn Error>> {
+    let pwm = hardware.actuator(Channel::Pwm0, PULSE_CLOSED_US, SERVO1_LIMITS)?;
+    let servo1 = servo::Servo::new(
+        SERVO1_LIMITS,
+        PULSE_CLOSED_US,
+        PULSE_OPEN_US,
+        PULSE_PASSED_US,
+        Default::default(),
+        Some(pwm.as_ref()),
+    )
+    .with_clock(clock);
+    servo1.close_and_disable(Duration::from_millis(SAFE_STATE_TRAVEL_MS))
+}
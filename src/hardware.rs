@@ -0,0 +1,83 @@
+use rppal::pwm::{Channel, Polarity, Pwm};
+use std::error::Error;
+use std::time::Duration;
+
+use crate::servo::{Actuator, PulseLimits};
+
+pub const PERIOD_MS: u64 = 20;
+pub const PULSE_OPEN_US: u64 = 1850;
+pub const PULSE_CLOSED_US: u64 = 2400;
+pub const PULSE_PASSED_US: u64 = 2650;
+pub const PULSE_MIN_US: u64 = 1800;
+pub const PULSE_MAX_US: u64 = 2700;
+pub const SERVO1_LIMITS: PulseLimits = PulseLimits {
+    min: PULSE_MIN_US,
+    max: PULSE_MAX_US,
+};
+// servo 2 is currently disconnected
+pub const PULSE_CLOSED_1_US: u64 = 1440;
+pub const PULSE_OPEN_1_US: u64 = 860;
+pub const PULSE_PASSED_1_US: u64 = 1700;
+pub const PULSE_MIN_1_US: u64 = 800;
+pub const PULSE_MAX_1_US: u64 = 1750;
+pub const SERVO2_LIMITS: PulseLimits = PulseLimits {
+    min: PULSE_MIN_1_US,
+    max: PULSE_MAX_1_US,
+};
+pub const SAFE_STATE_TRAVEL_MS: u64 = 500;
+pub const HARDWARE_RETRY_MIN_S: u64 = 10;
+pub const HARDWARE_RETRY_MAX_S: u64 = 600;
+
+/// Creates the PWM for a servo resting at `pulse_closed`, which has to be
+/// within the servo's limits.
+pub fn create_pwm(
+    channel: Channel,
+    pulse_closed: u64,
+    limits: PulseLimits,
+) -> Result<Pwm, Box<dyn Error>> {
+    limits.check(pulse_closed)?;
+    Ok(Pwm::with_period(
+        channel,
+        Duration::from_millis(PERIOD_MS),
+        Duration::from_micros(pulse_closed),
+        Polarity::Normal,
+        true,
+    )?)
+}
+
+/// Hands out the actuators driving the servos, so feeding can run against
+/// something other than the Pi.
+pub trait Hardware {
+    fn actuator(
+        &self,
+        channel: Channel,
+        pulse_closed: u64,
+        limits: PulseLimits,
+    ) -> Result<Box<dyn Actuator + '_>, Box<dyn Error>>;
+}
+
+/// The hardware PWM of the Pi.
+pub struct PwmHardware;
+
+impl Hardware for PwmHardware {
+    fn actuator(
+        &self,
+        channel: Channel,
+        pulse_closed: u64,
+        limits: PulseLimits,
+    ) -> Result<Box<dyn Actuator + '_>, Box<dyn Error>> {
+        Ok(Box::new(create_pwm(channel, pulse_closed, limits)?))
+    }
+}
+
+/// Checks that the PWM of servo 1 can be set up, leaving the lid closed.
+/// Returns the fault if it can't.
+pub fn probe_hardware(hardware: &dyn Hardware) -> Option<String> {
+    match hardware.actuator(Channel::Pwm0, PULSE_CLOSED_US, SERVO1_LIMITS) {
+        Ok(pwm) => {
+            pwm.disable().unwrap_or(());
+            None
+        }
+        Err(e) => Some(e.to_string()),
+    }
+}
@@ -0,0 +1,324 @@
+use chrono::prelude::*;
+use log::warn;
+use serde::{Deserialize, Serialize};
+use std::fs::{File, OpenOptions};
+use std::io::prelude::*;
+use std::io::BufReader;
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum Trigger {
+    Scheduled,
+    Manual,
+    Recovery,
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum Outcome {
+    Fed,
+    Failed,
+    RationRefused,
+    BreakerTripped,
+    /// Paused or skipped on request, the lid stayed shut.
+    Skipped,
+}
+
+impl Trigger {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Trigger::Scheduled => "scheduled",
+            Trigger::Manual => "manual",
+            Trigger::Recovery => "recovery",
+        }
+    }
+}
+
+impl Outcome {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Outcome::Fed => "fed",
+            Outcome::Failed => "failed",
+            Outcome::RationRefused => "ration_refused",
+            Outcome::BreakerTripped => "breaker_tripped",
+            Outcome::Skipped => "skipped",
+        }
+    }
+}
+
+/// One feed attempt, whether or not the lid was opened.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct Entry {
+    pub time: DateTime<FixedOffset>,
+    pub trigger: Trigger,
+    pub occasion: String,
+    pub hopper: String,
+    pub requested_ms: u64,
+    pub open_ms: u64,
+    pub outcome: Outcome,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+    /// Which try at feeding the occasion this was, counting from 1.
+    #[serde(default = "first_attempt")]
+    pub attempt: u32,
+    #[serde(default)]
+    pub elapsed_ms: u64,
+    #[serde(default)]
+    pub jiggle_cycles: u32,
+}
+
+fn first_attempt() -> u32 {
+    1
+}
+
+impl Entry {
+    pub fn date(&self) -> NaiveDate {
+        self.time.with_timezone(&Local).date().naive_local()
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub enum Format {
+    Text,
+    Csv,
+    Json,
+}
+
+/// What `picat history` was asked to show.
+#[derive(Debug, PartialEq)]
+pub struct Query {
+    pub from: Option<NaiveDate>,
+    pub to: Option<NaiveDate>,
+    pub format: Format,
+}
+
+impl Query {
+    /// Parses `[--from YYYY-MM-DD] [--to YYYY-MM-DD] [--format text|csv|json]`.
+    pub fn parse(args: &[String]) -> Result<Query, String> {
+        let mut query = Query {
+            from: None,
+            to: None,
+            format: Format::Text,
+        };
+        let mut args = args.iter();
+        while let Some(arg) = args.next() {
+            let value = args
+                .next()
+                .ok_or_else(|| format!("Missing value for {}", arg))?;
+            match arg.as_str() {
+                "--from" => query.from = Some(parse_date(value)?),
+                "--to" => query.to = Some(parse_date(value)?),
+                "--format" => {
+                    query.format = match value.as_str() {
+                        "text" => Format::Text,
+                        "csv" => Format::Csv,
+                        "json" => Format::Json,
+                        _ => return Err(format!("Unknown format {}", value)),
+                    }
+                }
+                _ => return Err(format!("Unknown option {}", arg)),
+            }
+        }
+        Ok(query)
+    }
+
+    /// Whether the entry falls within the dates asked for, both inclusive.
+    pub fn matches(&self, entry: &Entry) -> bool {
+        let date = entry.date();
+        self.from.is_none_or(|from| date >= from) && self.to.is_none_or(|to| date <= to)
+    }
+}
+
+pub(crate) fn parse_date(value: &str) -> Result<NaiveDate, String> {
+    NaiveDate::parse_from_str(value, "%Y-%m-%d")
+        .map_err(|_| format!("Invalid date {}, expected YYYY-MM-DD", value))
+}
+
+fn csv_field(value: &str) -> String {
+    if value.contains([',', '"', '\n']) {
+        format!("\"{}\"", value.replace('"', "\"\""))
+    } else {
+        String::from(value)
+    }
+}
+
+pub fn to_csv(entries: &[Entry]) -> String {
+    let mut csv =
+        String::from("time,trigger,occasion,hopper,requested_ms,open_ms,outcome,error,attempt\n");
+    for entry in entries.iter() {
+        csv.push_str(&format!(
+            "{},{},{},{},{},{},{},{},{}\n",
+            entry.time.to_rfc3339(),
+            entry.trigger.as_str(),
+            csv_field(&entry.occasion),
+            csv_field(&entry.hopper),
+            entry.requested_ms,
+            entry.open_ms,
+            entry.outcome.as_str(),
+            csv_field(entry.error.as_deref().unwrap_or("")),
+            entry.attempt
+        ));
+    }
+    csv
+}
+
+pub fn to_text(entries: &[Entry]) -> String {
+    let mut text = String::new();
+    for entry in entries.iter() {
+        text.push_str(&format!(
+            "{} {:<7} {:<8} requested {} ms, opened {} ms: {}",
+            entry.time.format("%Y-%m-%d %H:%M:%S"),
+            entry.occasion,
+            entry.hopper,
+            entry.requested_ms,
+            entry.open_ms,
+            entry.outcome.as_str()
+        ));
+        if let Some(ref error) = entry.error {
+            text.push_str(&format!(" ({})", error));
+        }
+        if entry.attempt > 1 {
+            text.push_str(&format!(", attempt {}", entry.attempt));
+        }
+        text.push('\n');
+    }
+    text
+}
+
+/// Appends an entry as one JSON line and makes sure it reaches the disk.
+pub fn append(file_path: &str, entry: &Entry) -> Result<(), std::io::Error> {
+    let mut line = serde_json::to_vec(entry)?;
+    line.push(b'\n');
+    let mut file = OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(file_path)?;
+    file.write_all(&line)?;
+    file.sync_data()
+}
+
+/// Reads all entries, skipping lines that can't be parsed, like one cut
+/// short by a power loss.
+pub fn load(file_path: &str) -> Result<Vec<Entry>, std::io::Error> {
+    let file = File::open(file_path)?;
+    let mut entries = Vec::new();
+    for (number, line) in BufReader::new(file).lines().enumerate() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        match serde_json::from_str(&line) {
+            Ok(entry) => entries.push(entry),
+            Err(e) => warn!(line = number + 1, error:% = e; "Skipping unreadable history entry"),
+        }
+    }
+    Ok(entries)
+}
+
+/// Loads the entries `query` asks for, none if nothing was fed yet.
+pub fn select(file_path: &str, query: &Query) -> Result<Vec<Entry>, std::io::Error> {
+    let entries = match load(file_path) {
+        Ok(entries) => entries,
+        Err(ref e) if e.kind() == std::io::ErrorKind::NotFound => Vec::new(),
+        Err(e) => return Err(e),
+    };
+    Ok(entries
+        .into_iter()
+        .filter(|entry| query.matches(entry))
+        .collect())
+}
+
+#[cfg(test)]
+fn entry(time: DateTime<Local>, outcome: Outcome, error: Option<&str>) -> Entry {
+    Entry {
+        time: time.into(),
+        trigger: Trigger::Scheduled,
+        occasion: String::from("04:25"),
+        hopper: String::from("servo1"),
+        requested_ms: 320,
+        open_ms: 320,
+        outcome,
+        error: error.map(String::from),
+        attempt: 1,
+        elapsed_ms: 1520,
+        jiggle_cycles: 3,
+    }
+}
+
+#[test]
+fn appends_and_skips_torn_lines() {
+    let path = std::env::temp_dir().join(format!("picat-history-{}.jsonl", std::process::id()));
+    let path = path.to_str().unwrap();
+    let _ = std::fs::remove_file(path);
+    let fed = entry(Local.ymd(2019, 9, 1).and_hms(4, 25, 0), Outcome::Fed, None);
+    let failed = entry(
+        Local.ymd(2019, 9, 1).and_hms(7, 30, 0),
+        Outcome::Failed,
+        Some("lid stuck"),
+    );
+
+    append(path, &fed).unwrap();
+    OpenOptions::new()
+        .append(true)
+        .open(path)
+        .unwrap()
+        .write_all(b"{\"time\":\"2019-09\n")
+        .unwrap();
+    append(path, &failed).unwrap();
+
+    assert_eq!(vec![fed, failed], load(path).unwrap());
+    std::fs::remove_file(path).unwrap();
+}
+
+#[test]
+fn query_filters_by_date() {
+    let args: Vec<String> = vec![
+        "--from",
+        "2019-09-02",
+        "--to",
+        "2019-09-03",
+        "--format",
+        "csv",
+    ]
+    .into_iter()
+    .map(String::from)
+    .collect();
+    let query = Query::parse(&args).unwrap();
+
+    assert_eq!(Format::Csv, query.format);
+    assert!(!query.matches(&entry(
+        Local.ymd(2019, 9, 1).and_hms(23, 59, 0),
+        Outcome::Fed,
+        None
+    )));
+    assert!(query.matches(&entry(
+        Local.ymd(2019, 9, 2).and_hms(0, 0, 0),
+        Outcome::Fed,
+        None
+    )));
+    assert!(query.matches(&entry(
+        Local.ymd(2019, 9, 3).and_hms(23, 59, 0),
+        Outcome::Fed,
+        None
+    )));
+    assert!(!query.matches(&entry(
+        Local.ymd(2019, 9, 4).and_hms(0, 0, 0),
+        Outcome::Fed,
+        None
+    )));
+    assert!(Query::parse(&[String::from("--from")]).is_err());
+}
+
+#[test]
+fn csv_quotes_errors() {
+    let failed = entry(
+        Local.ymd(2019, 9, 1).and_hms(7, 30, 0),
+        Outcome::Failed,
+        Some("pulse 2800, out of range"),
+    );
+
+    let csv = to_csv(&[failed]);
+
+    let row = csv.lines().nth(1).unwrap();
+    assert!(row.ends_with(",scheduled,04:25,servo1,320,320,failed,\"pulse 2800, out of range\",1"));
+}
@@ -0,0 +1,111 @@
+use serde::{Deserialize, Serialize};
+
+use crate::servo::Servo;
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum Position {
+    Open,
+    Closed,
+    Passed,
+    Pulse(u64),
+}
+
+impl Position {
+    pub fn pulse(&self, servo: &Servo) -> u64 {
+        match *self {
+            Position::Open => servo.pulse_open,
+            Position::Closed => servo.pulse_closed,
+            Position::Passed => servo.pulse_passed,
+            Position::Pulse(pulse) => pulse,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+pub struct JiggleStep {
+    pub position: Position,
+    pub dwell_ms: u64,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct Shake {
+    pub repetitions: u32,
+    pub steps: Vec<JiggleStep>,
+}
+
+/// Agitation of the lid to keep kibble from clogging the opening.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct JigglePattern {
+    #[serde(default)]
+    pub pre_open: Option<Shake>,
+    #[serde(default)]
+    pub after_open: Option<Shake>,
+}
+
+impl Default for JigglePattern {
+    fn default() -> Self {
+        JigglePattern {
+            pre_open: None,
+            after_open: Some(Shake {
+                repetitions: 3,
+                steps: vec![
+                    JiggleStep {
+                        position: Position::Passed,
+                        dwell_ms: 200,
+                    },
+                    JiggleStep {
+                        position: Position::Closed,
+                        dwell_ms: 200,
+                    },
+                ],
+            }),
+        }
+    }
+}
+
+impl JigglePattern {
+    /// Checks that every position of the pattern is within the servo's limits.
+    pub fn validate(&self, servo: &Servo) -> Result<(), String> {
+        let shakes = self.pre_open.iter().chain(self.after_open.iter());
+        for step in shakes.flat_map(|shake| shake.steps.iter()) {
+            if let Err(e) = servo.limits.check(step.position.pulse(servo)) {
+                return Err(e.to_string());
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+fn test_servo() -> Servo<'static> {
+    let limits = crate::servo::PulseLimits {
+        min: 1800,
+        max: 2700,
+    };
+    Servo::new(limits, 2400, 1850, 2650, Default::default(), None)
+}
+
+#[test]
+fn default_pattern_is_valid() {
+    let servo = test_servo();
+
+    assert!(JigglePattern::default().validate(&servo).is_ok());
+}
+
+#[test]
+fn pulse_outside_limits_is_invalid() {
+    let servo = test_servo();
+    let pattern = JigglePattern {
+        pre_open: Some(Shake {
+            repetitions: 1,
+            steps: vec![JiggleStep {
+                position: Position::Pulse(2900),
+                dwell_ms: 100,
+            }],
+        }),
+        after_open: None,
+    };
+
+    assert!(pattern.validate(&servo).is_err());
+}
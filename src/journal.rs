@@ -0,0 +1,225 @@
+use chrono::prelude::*;
+use log::warn;
+use serde::{Deserialize, Serialize};
+use std::fs::{File, OpenOptions};
+use std::io::prelude::*;
+use std::io::BufReader;
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+#[serde(rename_all = "snake_case")]
+enum Event {
+    Begin,
+    Complete,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+struct Record {
+    event: Event,
+    date: NaiveDate,
+    occasion: String,
+    time: DateTime<FixedOffset>,
+}
+
+/// A dispense of an occasion on a given day.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Dispense {
+    pub date: NaiveDate,
+    pub occasion: String,
+    pub started_at: DateTime<FixedOffset>,
+    pub completed: bool,
+}
+
+/// Write-ahead journal of dispenses. A dispense is begun before the lid is
+/// touched and completed afterwards, so a restart can tell which occasions
+/// were already handled and which were interrupted.
+pub struct Journal {
+    path: String,
+    dispenses: Vec<Dispense>,
+}
+
+impl Journal {
+    /// Replays the journal at `file_path`, which doesn't have to exist yet.
+    pub fn open(file_path: &str) -> Result<Journal, std::io::Error> {
+        let mut journal = Journal {
+            path: String::from(file_path),
+            dispenses: Vec::new(),
+        };
+        let file = match File::open(file_path) {
+            Ok(file) => file,
+            Err(ref e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(journal),
+            Err(e) => return Err(e),
+        };
+        for (number, line) in BufReader::new(file).lines().enumerate() {
+            let line = line?;
+            if line.trim().is_empty() {
+                continue;
+            }
+            match serde_json::from_str(&line) {
+                Ok(record) => journal.apply(record),
+                Err(e) => {
+                    warn!(line = number + 1, error:% = e; "Skipping unreadable journal record")
+                }
+            }
+        }
+        Ok(journal)
+    }
+
+    fn apply(&mut self, record: Record) {
+        match record.event {
+            Event::Begin => self.dispenses.push(Dispense {
+                date: record.date,
+                occasion: record.occasion,
+                started_at: record.time,
+                completed: false,
+            }),
+            Event::Complete => {
+                let date = record.date;
+                let occasion = record.occasion;
+                if let Some(dispense) = self
+                    .dispenses
+                    .iter_mut()
+                    .rev()
+                    .find(|d| d.date == date && d.occasion == occasion)
+                {
+                    dispense.completed = true;
+                }
+            }
+        }
+    }
+
+    fn write(&mut self, record: Record) -> Result<(), std::io::Error> {
+        let mut line = serde_json::to_vec(&record)?;
+        line.push(b'\n');
+        let mut file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.path)?;
+        file.write_all(&line)?;
+        file.sync_data()?;
+        self.apply(record);
+        Ok(())
+    }
+
+    /// Whether a dispense of the occasion was begun on `date`, whether or not
+    /// it completed.
+    pub fn is_started(&self, date: NaiveDate, occasion: &str) -> bool {
+        self.dispenses
+            .iter()
+            .any(|d| d.date == date && d.occasion == occasion)
+    }
+
+    /// Dispenses that were begun but never completed.
+    pub fn incomplete(&self) -> Vec<Dispense> {
+        self.dispenses
+            .iter()
+            .filter(|d| !d.completed)
+            .cloned()
+            .collect()
+    }
+
+    /// Records that the occasion is about to be dispensed. Has to be on disk
+    /// before the lid moves.
+    pub fn begin(
+        &mut self,
+        date: NaiveDate,
+        occasion: &str,
+        now: DateTime<Local>,
+    ) -> Result<(), std::io::Error> {
+        self.write(Record {
+            event: Event::Begin,
+            date,
+            occasion: String::from(occasion),
+            time: now.into(),
+        })
+    }
+
+    pub fn complete(
+        &mut self,
+        date: NaiveDate,
+        occasion: &str,
+        now: DateTime<Local>,
+    ) -> Result<(), std::io::Error> {
+        self.write(Record {
+            event: Event::Complete,
+            date,
+            occasion: String::from(occasion),
+            time: now.into(),
+        })
+    }
+
+    /// Drops completed dispenses from before `since`, replacing the file
+    /// atomically.
+    pub fn compact(&mut self, since: NaiveDate) -> Result<(), std::io::Error> {
+        self.dispenses.retain(|d| !d.completed || d.date >= since);
+
+        let mut content = Vec::new();
+        for dispense in self.dispenses.iter() {
+            let mut record = Record {
+                event: Event::Begin,
+                date: dispense.date,
+                occasion: dispense.occasion.clone(),
+                time: dispense.started_at,
+            };
+            content.extend_from_slice(&serde_json::to_vec(&record)?);
+            content.push(b'\n');
+            if dispense.completed {
+                record.event = Event::Complete;
+                content.extend_from_slice(&serde_json::to_vec(&record)?);
+                content.push(b'\n');
+            }
+        }
+
+        crate::write_atomically(&self.path, &content)
+    }
+}
+
+#[cfg(test)]
+fn temp_journal(name: &str) -> String {
+    let path = std::env::temp_dir().join(format!("picat-{}-{}.jsonl", name, std::process::id()));
+    let _ = std::fs::remove_file(&path);
+    path.to_string_lossy().into_owned()
+}
+
+#[test]
+fn interrupted_dispense_is_incomplete_after_reopen() {
+    let path = temp_journal("journal-reopen");
+    let date = NaiveDate::from_ymd(2019, 9, 1);
+    let now = Local.ymd(2019, 9, 1).and_hms(7, 30, 0);
+
+    let mut journal = Journal::open(&path).unwrap();
+    journal.begin(date, "04:25", now).unwrap();
+    journal.complete(date, "04:25", now).unwrap();
+    journal.begin(date, "07:30", now).unwrap();
+
+    let journal = Journal::open(&path).unwrap();
+    assert!(journal.is_started(date, "04:25"));
+    assert!(journal.is_started(date, "07:30"));
+    assert!(!journal.is_started(date, "08:30"));
+    let incomplete = journal.incomplete();
+    assert_eq!(1, incomplete.len());
+    assert_eq!("07:30", incomplete[0].occasion);
+    std::fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn compact_keeps_recent_and_incomplete() {
+    let path = temp_journal("journal-compact");
+    let old = NaiveDate::from_ymd(2019, 8, 30);
+    let today = NaiveDate::from_ymd(2019, 9, 1);
+    let now = Local.ymd(2019, 9, 1).and_hms(7, 30, 0);
+
+    let mut journal = Journal::open(&path).unwrap();
+    journal.begin(old, "04:25", now).unwrap();
+    journal.complete(old, "04:25", now).unwrap();
+    journal.begin(old, "07:30", now).unwrap();
+    journal.begin(today, "04:25", now).unwrap();
+    journal.complete(today, "04:25", now).unwrap();
+    journal.compact(today).unwrap();
+
+    let journal = Journal::open(&path).unwrap();
+    assert!(!journal.is_started(old, "04:25"));
+    assert!(journal.is_started(old, "07:30"));
+    assert!(journal.is_started(today, "04:25"));
+    assert_eq!(1, journal.incomplete().len());
+    std::fs::remove_file(&path).unwrap();
+}
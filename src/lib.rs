@@ -0,0 +1,121 @@
+//! Feeds the cat. The `picat` daemon and the helper tools are thin binaries
+//! on top of this library.
+
+use std::fs::{self, File};
+use std::io::prelude::*;
+use std::path::{Path, PathBuf};
+
+pub mod actuation;
+pub mod alert;
+pub mod api;
+pub mod backoff;
+pub mod breaker;
+pub mod check;
+pub mod clock;
+pub mod config;
+pub mod control;
+#[cfg(any(test, feature = "testing"))]
+pub mod fault;
+pub mod feeder;
+pub mod hardware;
+pub mod history;
+pub mod jiggle;
+pub mod journal;
+pub mod lock;
+pub mod logging;
+pub mod motion;
+pub mod persistant_schedule_storage;
+pub mod ration;
+pub mod report;
+pub mod safe_state;
+pub mod schedule;
+pub mod scheduler;
+pub mod sd_notify;
+pub mod servo;
+pub mod signals;
+pub mod simulate;
+pub mod state;
+pub mod status;
+pub mod trace;
+pub mod watchdog;
+
+// all files but the lock and socket live in the working directory
+pub const SCHEDULE_FILE_NAME: &str = "schedule.json";
+pub const CONFIG_FILE_NAME: &str = "config.json";
+pub const STATE_FILE_NAME: &str = "state.json";
+// written by earlier versions, moved into the state file on first start
+pub const LEGACY_RATION_FILE_NAME: &str = "ration.json";
+pub const LEGACY_BREAKER_FILE_NAME: &str = "breaker.json";
+pub const HISTORY_FILE_NAME: &str = "history.jsonl";
+pub const JOURNAL_FILE_NAME: &str = "journal.jsonl";
+pub const LOCK_FILE_NAME: &str = "picat.lock";
+pub const SOCKET_FILE_NAME: &str = "picat.sock";
+
+/// Where the hardware lock and the control socket are, unless `PICAT_DIR`
+/// says otherwise. Every picat on the machine has to find them in the same
+/// place, so they don't follow the working directory like the other files.
+pub const STATE_DIR: &str = "/var/lib/picat";
+
+/// The directory of the hardware lock and the control socket, as an
+/// absolute path.
+pub fn state_dir() -> PathBuf {
+    let dir = std::env::var_os("PICAT_DIR")
+        .map(PathBuf::from)
+        .unwrap_or_else(|| PathBuf::from(STATE_DIR));
+    // a relative one would differ between working directories again
+    match std::env::current_dir() {
+        Ok(cwd) => cwd.join(dir),
+        Err(_) => dir,
+    }
+}
+
+/// Where picat keeps the files it reads and writes while feeding. The daemon
+/// keeps them in the working directory and the lock and socket in the state
+/// directory, the simulation and tests all of them in directories of their
+/// own.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Files {
+    pub schedule: String,
+    pub config: String,
+    pub state: String,
+    pub history: String,
+    pub journal: String,
+    pub lock: String,
+    pub socket: String,
+}
+
+impl Default for Files {
+    fn default() -> Self {
+        let shared = Files::in_dir(&state_dir());
+        Files {
+            lock: shared.lock,
+            socket: shared.socket,
+            ..Files::in_dir(Path::new(""))
+        }
+    }
+}
+
+impl Files {
+    pub fn in_dir(dir: &Path) -> Files {
+        let path = |name: &str| dir.join(name).to_string_lossy().into_owned();
+        Files {
+            schedule: path(SCHEDULE_FILE_NAME),
+            config: path(CONFIG_FILE_NAME),
+            state: path(STATE_FILE_NAME),
+            history: path(HISTORY_FILE_NAME),
+            journal: path(JOURNAL_FILE_NAME),
+            lock: path(LOCK_FILE_NAME),
+            socket: path(SOCKET_FILE_NAME),
+        }
+    }
+}
+
+/// Writes `contents` to a temporary file and renames it over `file_path`, so
+/// a power loss leaves either the old or the new file behind.
+pub fn write_atomically(file_path: &str, contents: &[u8]) -> Result<(), std::io::Error> {
+    let temp_path = format!("{}.tmp", file_path);
+    let mut file = File::create(&temp_path)?;
+    file.write_all(contents)?;
+    file.sync_all()?;
+    fs::rename(&temp_path, file_path)
+}
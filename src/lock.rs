@@ -0,0 +1,85 @@
+use std::error::Error;
+use std::fmt;
+use std::fs::{self, File, OpenOptions};
+use std::io::prelude::*;
+use std::os::unix::io::AsRawFd;
+use std::path::Path;
+
+/// Another process holds the hardware lock.
+#[derive(Debug)]
+pub struct AlreadyRunning {
+    pub path: String,
+    /// The process holding the lock, as far as the lock file tells.
+    pub pid: Option<u32>,
+}
+
+impl fmt::Display for AlreadyRunning {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.pid {
+            Some(pid) => write!(
+                f,
+                "Another picat (pid {}) is using the hardware ({} is locked), stop it first",
+                pid, self.path
+            ),
+            None => write!(
+                f,
+                "Another picat is using the hardware ({} is locked), stop it first",
+                self.path
+            ),
+        }
+    }
+}
+
+impl Error for AlreadyRunning {}
+
+/// An exclusive lock on the hardware, held until dropped. The kernel lets go
+/// of it when the process dies, so a crash never leaves a stale lock behind.
+#[derive(Debug)]
+pub struct HardwareLock {
+    _file: File,
+}
+
+/// Takes the hardware lock at `file_path` without waiting, failing with
+/// `AlreadyRunning` if another process has it. The directory is created if
+/// need be.
+pub fn acquire(file_path: &str) -> Result<HardwareLock, Box<dyn Error>> {
+    if let Some(dir) = Path::new(file_path).parent() {
+        fs::create_dir_all(dir)?;
+    }
+    // not truncated before locking, that would wipe the pid of the holder
+    let mut file = OpenOptions::new()
+        .read(true)
+        .write(true)
+        .create(true)
+        .truncate(false)
+        .open(file_path)?;
+    if unsafe { libc::flock(file.as_raw_fd(), libc::LOCK_EX | libc::LOCK_NB) } != 0 {
+        let e = std::io::Error::last_os_error();
+        if e.kind() != std::io::ErrorKind::WouldBlock {
+            return Err(e.into());
+        }
+        let mut contents = String::new();
+        file.read_to_string(&mut contents)?;
+        return Err(Box::new(AlreadyRunning {
+            path: String::from(file_path),
+            pid: contents.trim().parse().ok(),
+        }));
+    }
+    file.set_len(0)?;
+    writeln!(file, "{}", std::process::id())?;
+    Ok(HardwareLock { _file: file })
+}
+
+#[test]
+fn second_lock_fails_until_the_first_is_released() {
+    let path = std::env::temp_dir().join(format!("picat-lock-{}", std::process::id()));
+    let path = path.to_str().unwrap();
+
+    let lock = acquire(path).unwrap();
+    let error = acquire(path).unwrap_err();
+    let running = error.downcast_ref::<AlreadyRunning>().unwrap();
+    assert_eq!(Some(std::process::id()), running.pid);
+    drop(lock);
+    assert!(acquire(path).is_ok());
+    std::fs::remove_file(path).unwrap();
+}
@@ -0,0 +1,245 @@
+use chrono::prelude::*;
+use log::kv::{Key, Value, VisitSource};
+use log::{Level, LevelFilter, Log, Metadata, Record};
+use serde::{Deserialize, Serialize};
+use std::env;
+use std::fs::{File, OpenOptions};
+use std::io::prelude::*;
+use std::os::unix::net::UnixDatagram;
+use std::sync::{Mutex, OnceLock};
+
+const LEVEL_ENV: &str = "PICAT_LOG";
+const JOURNAL_SOCKET: &str = "/run/systemd/journal/socket";
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum LogOutput {
+    Stderr,
+    File(String),
+    Journal,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct LogConfig {
+    #[serde(default = "default_level")]
+    pub level: String,
+    #[serde(default = "default_output")]
+    pub output: LogOutput,
+}
+
+fn default_level() -> String {
+    String::from("info")
+}
+
+fn default_output() -> LogOutput {
+    LogOutput::Stderr
+}
+
+impl Default for LogConfig {
+    fn default() -> Self {
+        LogConfig {
+            level: default_level(),
+            output: default_output(),
+        }
+    }
+}
+
+enum Sink {
+    Stderr,
+    File(File),
+    Journal(UnixDatagram),
+}
+
+struct Logger {
+    sink: Mutex<Sink>,
+}
+
+static LOGGER: OnceLock<Logger> = OnceLock::new();
+
+/// The structured fields of a record, in the order they were given.
+struct Fields(Vec<(String, String)>);
+
+impl<'kvs> VisitSource<'kvs> for Fields {
+    fn visit_pair(&mut self, key: Key<'kvs>, value: Value<'kvs>) -> Result<(), log::kv::Error> {
+        self.0.push((key.to_string(), value.to_string()));
+        Ok(())
+    }
+}
+
+fn fields(record: &Record) -> Vec<(String, String)> {
+    let mut fields = Fields(Vec::new());
+    let _ = record.key_values().visit(&mut fields);
+    fields.0
+}
+
+/// Formats a record as a single `key=value` line.
+fn format_line(
+    time: DateTime<Local>,
+    level: Level,
+    message: &str,
+    fields: &[(String, String)],
+) -> String {
+    let mut line = format!("{} {:<5} {}", time.to_rfc3339(), level, message);
+    for (key, value) in fields.iter() {
+        if value.is_empty() || value.contains(char::is_whitespace) {
+            line.push_str(&format!(" {}={:?}", key, value));
+        } else {
+            line.push_str(&format!(" {}={}", key, value));
+        }
+    }
+    line
+}
+
+fn journal_priority(level: Level) -> u8 {
+    match level {
+        Level::Error => 3,
+        Level::Warn => 4,
+        Level::Info => 6,
+        Level::Debug | Level::Trace => 7,
+    }
+}
+
+fn push_journal_field(entry: &mut Vec<u8>, key: &str, value: &str) {
+    let key: String = key
+        .chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() {
+                c.to_ascii_uppercase()
+            } else {
+                '_'
+            }
+        })
+        .collect();
+    let key = key.trim_start_matches('_');
+
+    entry.extend_from_slice(key.as_bytes());
+    if value.contains('\n') {
+        entry.push(b'\n');
+        entry.extend_from_slice(&(value.len() as u64).to_le_bytes());
+        entry.extend_from_slice(value.as_bytes());
+    } else {
+        entry.push(b'=');
+        entry.extend_from_slice(value.as_bytes());
+    }
+    entry.push(b'\n');
+}
+
+/// Encodes a record in the native journal protocol.
+fn journal_entry(level: Level, message: &str, fields: &[(String, String)]) -> Vec<u8> {
+    let mut entry = Vec::new();
+    push_journal_field(&mut entry, "PRIORITY", &journal_priority(level).to_string());
+    push_journal_field(&mut entry, "SYSLOG_IDENTIFIER", "picat");
+    push_journal_field(&mut entry, "MESSAGE", message);
+    for (key, value) in fields.iter() {
+        push_journal_field(&mut entry, key, value);
+    }
+    entry
+}
+
+impl Log for Logger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        metadata.level() <= log::max_level()
+    }
+
+    fn log(&self, record: &Record) {
+        if !self.enabled(record.metadata()) {
+            return;
+        }
+        let message = record.args().to_string();
+        let fields = fields(record);
+        let line = format_line(Local::now(), record.level(), &message, &fields);
+
+        let mut sink = self.sink.lock().unwrap();
+        let result = match *sink {
+            Sink::Stderr => writeln!(std::io::stderr(), "{}", line),
+            Sink::File(ref mut file) => writeln!(file, "{}", line),
+            Sink::Journal(ref socket) => socket
+                .send_to(
+                    &journal_entry(record.level(), &message, &fields),
+                    JOURNAL_SOCKET,
+                )
+                .map(|_| ()),
+        };
+        if result.is_err() {
+            eprintln!("{}", line);
+        }
+    }
+
+    fn flush(&self) {
+        if let Sink::File(ref mut file) = *self.sink.lock().unwrap() {
+            let _ = file.flush();
+        }
+    }
+}
+
+fn level_from_env() -> Option<LevelFilter> {
+    env::var(LEVEL_ENV)
+        .ok()
+        .and_then(|level| level.parse().ok())
+}
+
+/// Starts logging to stderr at the level in `PICAT_LOG`, or info.
+pub fn init() {
+    let logger = LOGGER.get_or_init(|| Logger {
+        sink: Mutex::new(Sink::Stderr),
+    });
+    if log::set_logger(logger).is_ok() {
+        log::set_max_level(level_from_env().unwrap_or(LevelFilter::Info));
+    }
+}
+
+/// Switches level and output to what the config says. `PICAT_LOG` still
+/// overrides the level.
+pub fn configure(config: &LogConfig) -> Result<(), std::io::Error> {
+    let level = match level_from_env() {
+        Some(level) => level,
+        None => config.level.parse().map_err(|_| {
+            std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                format!("Unknown log level {}", config.level),
+            )
+        })?,
+    };
+    let sink = match config.output {
+        LogOutput::Stderr => Sink::Stderr,
+        LogOutput::File(ref path) => {
+            Sink::File(OpenOptions::new().create(true).append(true).open(path)?)
+        }
+        LogOutput::Journal => Sink::Journal(UnixDatagram::unbound()?),
+    };
+
+    if let Some(logger) = LOGGER.get() {
+        *logger.sink.lock().unwrap() = sink;
+    }
+    log::set_max_level(level);
+    Ok(())
+}
+
+#[test]
+fn line_has_fields() {
+    let time = Local.ymd(2019, 9, 1).and_hms(7, 30, 0);
+    let fields = vec![
+        (String::from("hopper"), String::from("servo1")),
+        (String::from("outcome"), String::from("failed: lid stuck")),
+    ];
+
+    let line = format_line(time, Level::Info, "Feed done", &fields);
+
+    assert!(line.ends_with(" INFO  Feed done hopper=servo1 outcome=\"failed: lid stuck\""));
+}
+
+#[test]
+fn journal_entry_encodes_fields() {
+    let fields = vec![
+        (String::from("open_ms"), String::from("320")),
+        (String::from("error"), String::from("two\nlines")),
+    ];
+
+    let entry = journal_entry(Level::Warn, "Feed failed", &fields);
+
+    let mut expected =
+        b"PRIORITY=4\nSYSLOG_IDENTIFIER=picat\nMESSAGE=Feed failed\nOPEN_MS=320\nERROR\n".to_vec();
+    expected.extend_from_slice(&9u64.to_le_bytes());
+    expected.extend_from_slice(b"two\nlines\n");
+    assert_eq!(expected, entry);
+}
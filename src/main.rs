@@ -1,458 +1,161 @@
-use chrono::prelude::*;
+use log::{error, info};
 
 use std::env;
 use std::error::Error;
-use std::thread;
-use std::time::Duration;
-
-// use actix_web::{web, App, HttpServer, Responder};
-use rppal::pwm::{Channel, Polarity, Pwm};
-
-mod persistant_schedule_storage;
-mod schedule;
-mod servo;
-
-const PERIOD_MS: u64 = 20;
-const PULSE_OPEN_US: u64 = 1850;
-const PULSE_CLOSED_US: u64 = 2400;
-const PULSE_PASSED_US: u64 = 2650;
-const PULSE_CLOSED_1_US: u64 = 1440;
-const PULSE_OPEN_1_US: u64 = 860;
-const PULSE_PASSED_1_US: u64 = 1700;
-const SCHEDULE_FILE_NAME: &str = "schedule.json";
-
-fn feed_cat(servo: &servo::Servo, feed_time: u64) -> Result<(), Box<dyn Error>> {
-    match servo.pwm {
-        Some(pwm) => {
-            pwm.set_pulse_width(Duration::from_micros(servo.pulse_open))?;
-            thread::sleep(Duration::from_millis(feed_time));
-
-            for _ in 1..4 {
-                pwm.set_pulse_width(Duration::from_micros(servo.pulse_passed))?;
-                thread::sleep(Duration::from_millis(200));
-                pwm.set_pulse_width(Duration::from_micros(servo.pulse_closed))?;
-                thread::sleep(Duration::from_millis(200));
-            }
-        }
-        None => {
-            println!("Was gonna feed the cat!");
-        }
+use std::process;
+
+use picat::Files;
+use picat::{config, history, logging, persistant_schedule_storage, schedule, scheduler};
+use picat::{control, lock, signals, simulate};
+
+const USAGE: &str = "Usage: picat [command]
+
+Without a command, runs the feeder daemon.
+
+Commands:
+  test                 feed once for a second to see the lid work
+  simulate [--from YYYY-MM-DD] [--days N] [--speed Nx]
+                       run the schedule against a virtual clock
+  history [--from YYYY-MM-DD] [--to YYYY-MM-DD] [--format text|csv|json]
+                       show the feeding history
+  reset-breaker        let feeding go on after the circuit breaker tripped
+  help                 show this";
+
+/// Prints the feeding history, filtered and formatted as asked for.
+fn show_history(args: &[String]) -> Result<(), Box<dyn Error>> {
+    let query = history::Query::parse(args)?;
+    let entries = history::select(&Files::default().history, &query)?;
+
+    match query.format {
+        history::Format::Text => print!("{}", history::to_text(&entries)),
+        history::Format::Csv => print!("{}", history::to_csv(&entries)),
+        history::Format::Json => println!("{}", serde_json::to_string_pretty(&entries)?),
     }
     Ok(())
 }
 
-fn create_default_schedule(schedule: &mut schedule::Schedule) {
-    schedule.push(schedule::Occasion {
-        time: Local.ymd(1970, 1, 1).and_hms(4, 25, 0),
-        enabled_weekdays: vec![
-            Weekday::Mon,
-            Weekday::Tue,
-            Weekday::Wed,
-            Weekday::Thu,
-            Weekday::Fri,
-            Weekday::Sat,
-            Weekday::Sun,
-        ],
-        opened_time_servo1: 320,
-        opened_time_servo2: 280,
-    });
-    schedule.push(schedule::Occasion {
-        time: Local.ymd(1970, 1, 1).and_hms(7, 30, 0),
-        enabled_weekdays: vec![
-            Weekday::Mon,
-            Weekday::Tue,
-            Weekday::Wed,
-            Weekday::Thu,
-            Weekday::Fri,
-            Weekday::Sat,
-            Weekday::Sun,
-        ],
-        opened_time_servo1: 320,
-        opened_time_servo2: 280,
-    });
-    schedule.push(schedule::Occasion {
-        time: Local.ymd(1970, 1, 1).and_hms(8, 30, 0),
-        enabled_weekdays: vec![
-            Weekday::Mon,
-            Weekday::Tue,
-            Weekday::Wed,
-            Weekday::Thu,
-            Weekday::Fri,
-            Weekday::Sat,
-            Weekday::Sun,
-        ],
-        opened_time_servo1: 320,
-        opened_time_servo2: 280,
-    });
-    schedule.push(schedule::Occasion {
-        time: Local.ymd(1970, 1, 1).and_hms(9, 30, 0),
-        enabled_weekdays: vec![
-            Weekday::Mon,
-            Weekday::Tue,
-            Weekday::Wed,
-            Weekday::Thu,
-            Weekday::Fri,
-            Weekday::Sat,
-            Weekday::Sun,
-        ],
-        opened_time_servo1: 320,
-        opened_time_servo2: 280,
-    });
-    schedule.push(schedule::Occasion {
-        time: Local.ymd(1970, 1, 1).and_hms(10, 30, 0),
-        enabled_weekdays: vec![
-            Weekday::Mon,
-            Weekday::Tue,
-            Weekday::Wed,
-            Weekday::Thu,
-            Weekday::Fri,
-            Weekday::Sat,
-            Weekday::Sun,
-        ],
-        opened_time_servo1: 320,
-        opened_time_servo2: 280,
-    });
-    schedule.push(schedule::Occasion {
-        time: Local.ymd(1970, 1, 1).and_hms(11, 30, 0),
-        enabled_weekdays: vec![
-            Weekday::Mon,
-            Weekday::Tue,
-            Weekday::Wed,
-            Weekday::Thu,
-            Weekday::Fri,
-            Weekday::Sat,
-            Weekday::Sun,
-        ],
-        opened_time_servo1: 320,
-        opened_time_servo2: 280,
-    });
-    schedule.push(schedule::Occasion {
-        time: Local.ymd(1970, 1, 1).and_hms(12, 30, 0),
-        enabled_weekdays: vec![
-            Weekday::Mon,
-            Weekday::Tue,
-            Weekday::Wed,
-            Weekday::Thu,
-            Weekday::Fri,
-            Weekday::Sat,
-            Weekday::Sun,
-        ],
-        opened_time_servo1: 320,
-        opened_time_servo2: 280,
-    });
-    schedule.push(schedule::Occasion {
-        time: Local.ymd(1970, 1, 1).and_hms(11, 30, 0),
-        enabled_weekdays: vec![
-            Weekday::Mon,
-            Weekday::Tue,
-            Weekday::Wed,
-            Weekday::Thu,
-            Weekday::Fri,
-            Weekday::Sat,
-            Weekday::Sun,
-        ],
-        opened_time_servo1: 320,
-        opened_time_servo2: 280,
-    });
-    schedule.push(schedule::Occasion {
-        time: Local.ymd(1970, 1, 1).and_hms(11, 30, 0),
-        enabled_weekdays: vec![
-            Weekday::Mon,
-            Weekday::Tue,
-            Weekday::Wed,
-            Weekday::Thu,
-            Weekday::Fri,
-            Weekday::Sat,
-            Weekday::Sun,
-        ],
-        opened_time_servo1: 320,
-        opened_time_servo2: 280,
-    });
-    schedule.push(schedule::Occasion {
-        time: Local.ymd(1970, 1, 1).and_hms(15, 30, 0),
-        enabled_weekdays: vec![
-            Weekday::Mon,
-            Weekday::Tue,
-            Weekday::Wed,
-            Weekday::Thu,
-            Weekday::Fri,
-            Weekday::Sat,
-            Weekday::Sun,
-        ],
-        opened_time_servo1: 320,
-        opened_time_servo2: 280,
-    });
-    schedule.push(schedule::Occasion {
-        time: Local.ymd(1970, 1, 1).and_hms(16, 30, 0),
-        enabled_weekdays: vec![
-            Weekday::Mon,
-            Weekday::Tue,
-            Weekday::Wed,
-            Weekday::Thu,
-            Weekday::Fri,
-            Weekday::Sat,
-            Weekday::Sun,
-        ],
-        opened_time_servo1: 320,
-        opened_time_servo2: 280,
-    });
-    schedule.push(schedule::Occasion {
-        time: Local.ymd(1970, 1, 1).and_hms(17, 30, 0),
-        enabled_weekdays: vec![
-            Weekday::Mon,
-            Weekday::Tue,
-            Weekday::Wed,
-            Weekday::Thu,
-            Weekday::Fri,
-            Weekday::Sat,
-            Weekday::Sun,
-        ],
-        opened_time_servo1: 320,
-        opened_time_servo2: 280,
-    });
-    schedule.push(schedule::Occasion {
-        time: Local.ymd(1970, 1, 1).and_hms(18, 30, 0),
-        enabled_weekdays: vec![
-            Weekday::Mon,
-            Weekday::Tue,
-            Weekday::Wed,
-            Weekday::Thu,
-            Weekday::Fri,
-            Weekday::Sat,
-            Weekday::Sun,
-        ],
-        opened_time_servo1: 320,
-        opened_time_servo2: 280,
-    });
-    schedule.push(schedule::Occasion {
-        time: Local.ymd(1970, 1, 1).and_hms(19, 30, 0),
-        enabled_weekdays: vec![
-            Weekday::Mon,
-            Weekday::Tue,
-            Weekday::Wed,
-            Weekday::Thu,
-            Weekday::Fri,
-            Weekday::Sat,
-            Weekday::Sun,
-        ],
-        opened_time_servo1: 320,
-        opened_time_servo2: 280,
-    });
-    schedule.push(schedule::Occasion {
-        time: Local.ymd(1970, 1, 1).and_hms(20, 30, 0),
-        enabled_weekdays: vec![
-            Weekday::Mon,
-            Weekday::Tue,
-            Weekday::Wed,
-            Weekday::Thu,
-            Weekday::Fri,
-            Weekday::Sat,
-            Weekday::Sun,
-        ],
-        opened_time_servo1: 320,
-        opened_time_servo2: 280,
-    });
+/// Simulates the schedule and config in the working directory, falling back to
+/// the defaults the feeder would create.
+fn simulate(args: &[String]) -> Result<(), Box<dyn Error>> {
+    let options = simulate::Options::parse(args)?;
+    let files = Files::default();
+    let config = match config::load(&files.config) {
+        Ok(config) => config,
+        Err(ref e) if e.kind() == std::io::ErrorKind::NotFound => config::Config::default(),
+        Err(e) => return Err(e.into()),
+    };
+    let schedule = match persistant_schedule_storage::load(&files.schedule) {
+        Ok(schedule) => schedule,
+        Err(ref e) if e.kind() == std::io::ErrorKind::NotFound => {
+            let mut schedule = schedule::Schedule::new();
+            scheduler::create_default_schedule(&mut schedule);
+            schedule
+        }
+        Err(e) => return Err(e.into()),
+    };
+    print!("{}", simulate::run(&options, schedule, config)?);
+    Ok(())
 }
 
-fn main_feeder_loop() -> Result<(), Box<dyn Error>> {
-    let mut schedule = schedule::Schedule::new();
-
-    let created_default = match persistant_schedule_storage::load(SCHEDULE_FILE_NAME) {
-        Ok(x) => {
-            println!("Persisted schedule found, using it");
-            schedule = x;
-            false
-        },
-        Err(_) =>  { 
-            println!("Persisted schedule doesnt exist, creating new");
-            create_default_schedule(&mut schedule);
-            true
+/// Resets the circuit breaker, through the running daemon if there is one
+/// so it doesn't write the state behind its back.
+fn reset_breaker() -> Result<String, Box<dyn Error>> {
+    let files = Files::default();
+    let response = match lock::acquire(&files.lock) {
+        Ok(_lock) => control::reset_breaker(&files),
+        Err(ref e) if e.is::<lock::AlreadyRunning>() => {
+            control::send(&files.socket, &control::Request::ResetBreaker)?
         }
+        Err(e) => return Err(e),
     };
-
-    if created_default {
-        match persistant_schedule_storage::save(SCHEDULE_FILE_NAME, &schedule) {
-            Ok(_) => {},
-            Err(_) => println!("Failed to persist schedule")
+    match response {
+        control::Response::Done { message } => Ok(message),
+        control::Response::Error { message } | control::Response::Failed { message } => {
+            Err(message.into())
         }
+        response => Err(format!("Unexpected response {:?}", response).into()),
     }
+}
 
-    loop {
-        let local = Local::now();
-
-        let result = schedule.contains(local);
-
-        if result.is_some() {
-            // let pwm1 = Pwm::with_period(
-            //     Channel::Pwm1,
-            //     Duration::from_millis(PERIOD_MS),
-            //     Duration::from_micros(PULSE_CLOSED_1_US),
-            //     Polarity::Normal,
-            //     true,
-            // );
-            // let servo2 = match pwm1 {
-            //     Ok(ref p) => servo::Servo {
-            //         pulse_closed: PULSE_CLOSED_1_US,
-            //         pulse_open: PULSE_OPEN_1_US,
-            //         pulse_passed: PULSE_PASSED_1_US,
-            //         pwm: Some(p),
-            //     },
-            //     Err(_) => {
-            //         println!("Failed to create servo2, using dummy");
-            //         servo::Servo {
-            //             pulse_closed: PULSE_CLOSED_US,
-            //             pulse_open: PULSE_OPEN_US,
-            //             pulse_passed: PULSE_PASSED_1_US,
-            //             pwm: None,
-            //         }
-            //     }
-            // };
-
-            // match feed_cat(&servo2, result.unwrap().opened_time_servo2) {
-            //     // 2900 tot
-            //     Ok(_) => println!("Fed the cat with servo 2"),
-            //     Err(_) => println!("Failed to feed the cat with servo 2"),
-            // }
-
-            // pwm1?.disable().unwrap_or(());
-            // thread::sleep(Duration::from_millis(3000));
-
-            let pwm = Pwm::with_period(
-                Channel::Pwm0,
-                Duration::from_millis(PERIOD_MS),
-                Duration::from_micros(PULSE_CLOSED_US),
-                Polarity::Normal,
-                true,
-            );
-
-            let servo1 = match pwm {
-                Ok(ref p) => servo::Servo {
-                    pulse_closed: PULSE_CLOSED_US,
-                    pulse_open: PULSE_OPEN_US,
-                    pulse_passed: PULSE_PASSED_US,
-                    pwm: Some(p),
-                },
-                Err(_) => {
-                    println!("Failed to create servo1, using dummy");
-                    servo::Servo {
-                        pulse_closed: PULSE_CLOSED_US,
-                        pulse_open: PULSE_OPEN_US,
-                        pulse_passed: PULSE_PASSED_US,
-                        pwm: None,
-                    }
-                }
-            };
-            match feed_cat(&servo1, result.unwrap().opened_time_servo1) {
-                // 2150 tot
-                Ok(_) => println!("Fed the cat with servo 1"),
-                Err(_) => println!("Failed to feed the cat with servo 1"),
-            }
-            pwm?.disable().unwrap_or(());
-
-            thread::sleep(Duration::from_millis(60000)) // make sure we never hit it the same minute
+/// Has the running daemon feed like the servo test does, rather than
+/// fighting over the PWM with it.
+fn forward_test() -> Result<(), Box<dyn Error>> {
+    let request = control::Request::FeedNow { ms: Some(1000) };
+    match control::send(&Files::default().socket, &request)? {
+        control::Response::Fed { report } => {
+            info!(outcome = report.outcome().as_str(); "Running picat fed");
+            Ok(())
         }
-        println!("Now {}", local);
-
-        thread::sleep(Duration::from_millis(30000))
+        control::Response::Error { message } | control::Response::Failed { message } => {
+            Err(message.into())
+        }
+        response => Err(format!("Unexpected response {:?}", response).into()),
     }
 }
 
-fn test_servo_loop() -> Result<(), Box<dyn Error>> {
-    // let pwm1 = Pwm::with_period(
-    //     Channel::Pwm1,
-    //     Duration::from_millis(PERIOD_MS),
-    //     Duration::from_micros(PULSE_CLOSED_1_US),
-    //     Polarity::Normal,
-    //     true,
-    // );
-    // let servo2 = match pwm1 {
-    //     Ok(ref p) => servo::Servo {
-    //         pulse_closed: PULSE_CLOSED_1_US,
-    //         pulse_open: PULSE_OPEN_1_US,
-    //         pulse_passed: PULSE_PASSED_1_US,
-    //         pwm: Some(p),
-    //     },
-    //     Err(_) => {
-    //         println!("Failed to create servo2, using dummy");
-    //         servo::Servo {
-    //             pulse_closed: PULSE_CLOSED_US,
-    //             pulse_open: PULSE_OPEN_US,
-    //             pulse_passed: PULSE_PASSED_1_US,
-    //             pwm: None,
-    //         }
-    //     }
-    // };
-
-    // match feed_cat(&servo2, 1000) {
-    //     // 2900 tot
-    //     Ok(_) => println!("Fed the cat with servo 2"),
-    //     Err(_) => println!("Failed to feed the cat with servo 2"),
-    // }
-    // pwm1?.disable().unwrap_or(());
-    // thread::sleep(Duration::from_millis(3000));
-    let pwm = Pwm::with_period(
-        Channel::Pwm0,
-        Duration::from_millis(PERIOD_MS),
-        Duration::from_micros(PULSE_CLOSED_US),
-        Polarity::Normal,
-        true,
-    );
-    let servo1 = match pwm {
-        Ok(ref p) => servo::Servo {
-            pulse_closed: PULSE_CLOSED_US,
-            pulse_open: PULSE_OPEN_US,
-            pulse_passed: PULSE_PASSED_US,
-            pwm: Some(p),
-        },
-        Err(_) => {
-            println!("Failed to create servo1, using dummy");
-            servo::Servo {
-                pulse_closed: PULSE_CLOSED_US,
-                pulse_open: PULSE_OPEN_US,
-                pulse_passed: PULSE_PASSED_US,
-                pwm: None,
-            }
-        }
-    };
-
-    match feed_cat(&servo1, 1000) {
-        // 2150 tot
-        Ok(_) => println!("Fed the cat with servo 1"),
-        Err(_) => println!("Failed to feed the cat with servo 1"),
+/// Only for the commands that wait, the others are left to be stopped the
+/// usual way.
+fn handle_signals() {
+    if let Err(e) = signals::register() {
+        error!(error:% = e; "Failed to register signal handlers");
     }
-    pwm?.disable().unwrap_or(());
-    Ok(())
 }
 
-// fn index(info: web::Path<(u32, String)>) -> impl Responder {
-//     format!("Hello {}! id: {}!", info.1, info.0)
-// }
-
 fn main() -> std::io::Result<()> {
     let args: Vec<String> = env::args().collect();
+    logging::init();
 
-    match args.len() {
-        2 => {
-            println!("Running servo test");
-            match test_servo_loop() {
-                Ok(_) => println!("Exited successfully"),
-                Err(_) => println!("Error happened"),
+    match args.get(1).map(String::as_str) {
+        Some("reset-breaker") => {
+            match reset_breaker() {
+                Ok(message) => info!("{}", message),
+                Err(e) => error!(error:% = e; "Failed to reset circuit breaker"),
+            };
+            Ok(())
+        }
+        Some("history") => {
+            if let Err(e) = show_history(&args[2..]) {
+                error!(error:% = e; "Failed to show feeding history");
+            }
+            Ok(())
+        }
+        Some("simulate") => {
+            handle_signals();
+            if let Err(e) = simulate(&args[2..]) {
+                error!(error:% = e; "Simulation failed");
+            }
+            Ok(())
+        }
+        Some("test") => {
+            handle_signals();
+            info!("Running servo test");
+            match scheduler::test_servo_loop() {
+                Ok(_) => info!("Exited successfully"),
+                Err(ref e) if e.is::<lock::AlreadyRunning>() => {
+                    info!(error:% = e; "Asking the running picat to feed instead");
+                    if let Err(e) = forward_test() {
+                        error!(error:% = e; "Servo test failed");
+                    }
+                }
+                Err(e) => error!(error:% = e; "Servo test failed"),
             };
             Ok(())
         }
-        _ => {
-            // thread::spawn(|| {
-            println!("Running feeder loop");
-            match main_feeder_loop() {
-                Ok(_) => println!("Exited successdully"),
-                Err(_) => println!("Error happened"),
+        Some("help") | Some("--help") | Some("-h") => {
+            println!("{}", USAGE);
+            Ok(())
+        }
+        Some(command) => {
+            eprintln!("Unknown command {}\n\n{}", command, USAGE);
+            process::exit(2);
+        }
+        None => {
+            handle_signals();
+            info!("Running feeder loop");
+            match scheduler::main_feeder_loop() {
+                Ok(_) => info!("Exited successfully"),
+                Err(e) => error!(error:% = e; "Feeder loop failed"),
             }
-            // });
-            // HttpServer::new(|| {
-            //     App::new().service(web::resource("/{id}/{name}/index.html").to(index))
-            // })
-            // .bind("192.168.1.187:8080")?
-            // .run()
             Ok(())
         }
     }
@@ -0,0 +1,85 @@
+use serde::{Deserialize, Serialize};
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum Easing {
+    Instant,
+    Linear,
+    EaseInOut,
+}
+
+/// Describes how a servo travels between two pulse widths.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+pub struct MotionProfile {
+    pub easing: Easing,
+    pub duration_ms: u64,
+    pub step_ms: u64,
+}
+
+impl Default for MotionProfile {
+    fn default() -> Self {
+        MotionProfile {
+            easing: Easing::Instant,
+            duration_ms: 0,
+            step_ms: 20,
+        }
+    }
+}
+
+impl MotionProfile {
+    /// Returns the pulse widths to command, one per `step_ms`, ending at `to`.
+    pub fn steps(&self, from: u64, to: u64) -> Vec<u64> {
+        if self.easing == Easing::Instant || self.duration_ms == 0 || self.step_ms == 0 {
+            return vec![to];
+        }
+
+        let count = (self.duration_ms / self.step_ms).max(1);
+        (1..=count)
+            .map(|i| {
+                let progress = self.ease(i as f64 / count as f64);
+                let pulse = from as f64 + (to as f64 - from as f64) * progress;
+                pulse.round() as u64
+            })
+            .collect()
+    }
+
+    fn ease(&self, t: f64) -> f64 {
+        match self.easing {
+            Easing::Instant => 1.0,
+            Easing::Linear => t,
+            Easing::EaseInOut => 0.5 - 0.5 * (std::f64::consts::PI * t).cos(),
+        }
+    }
+}
+
+#[test]
+fn instant_jumps_to_target() {
+    let profile = MotionProfile::default();
+
+    assert_eq!(vec![1850], profile.steps(2400, 1850));
+}
+
+#[test]
+fn linear_ramp() {
+    let profile = MotionProfile {
+        easing: Easing::Linear,
+        duration_ms: 100,
+        step_ms: 25,
+    };
+
+    assert_eq!(vec![2263, 2125, 1988, 1850], profile.steps(2400, 1850));
+}
+
+#[test]
+fn ease_in_out_is_slow_at_the_ends() {
+    let profile = MotionProfile {
+        easing: Easing::EaseInOut,
+        duration_ms: 200,
+        step_ms: 20,
+    };
+    let steps = profile.steps(1000, 2000);
+
+    assert_eq!(10, steps.len());
+    assert_eq!(2000, *steps.last().unwrap());
+    assert!(steps[0] - 1000 < steps[5] - steps[4]);
+}
@@ -4,13 +4,19 @@ use std::io::prelude::*;
 // use serde_json::Result;
 use chrono::prelude::*;
 
+use crate::jiggle::JigglePattern;
+
 #[derive(Serialize, Deserialize)]
 struct PersistedSchedule {
     enabled: bool,
     time: String,
     enabled_weekdays: Vec<u32>,
     opened_time_servo1: u64,
-    opened_time_servo2: u64
+    opened_time_servo2: u64,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    jiggle_servo1: Option<JigglePattern>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    jiggle_servo2: Option<JigglePattern>,
 }
 
 fn weekday_to_int(weekday: Weekday) -> u32 {
@@ -50,7 +56,9 @@ pub fn serialize(schedule: &crate::schedule::Schedule) -> String {
             time: time.time.to_rfc3339(),
             enabled_weekdays: enabled_weekdays_int,
             opened_time_servo1: time.opened_time_servo1,
-            opened_time_servo2: time.opened_time_servo2
+            opened_time_servo2: time.opened_time_servo2,
+            jiggle_servo1: time.jiggle_servo1.clone(),
+            jiggle_servo2: time.jiggle_servo2.clone(),
         });
     }
     serde_json::to_string(&persisted_schedule).unwrap()
@@ -65,24 +73,27 @@ pub fn deserialize(json: &str, schedule: &mut crate::schedule::Schedule) -> Resu
                 for wkday in sched.enabled_weekdays.iter() {
                     enabled_weekdays.push(int_to_weekday(*wkday));
                 }
-                let timestamp = sched.time.parse::<DateTime<Local>>().unwrap();
+                let timestamp = match sched.time.parse::<DateTime<Local>>() {
+                    Ok(timestamp) => timestamp,
+                    Err(_) => return Err(format!("Invalid time {}", sched.time)),
+                };
                 schedule.push(crate::schedule::Occasion {
                     time: timestamp,
                     enabled_weekdays,
                     opened_time_servo1: sched.opened_time_servo1,
-                    opened_time_servo2: sched.opened_time_servo2
+                    opened_time_servo2: sched.opened_time_servo2,
+                    jiggle_servo1: sched.jiggle_servo1.clone(),
+                    jiggle_servo2: sched.jiggle_servo2.clone(),
                 });
             }
             Ok(())
         }
-        Err(_) => Err(String::from("Error deserializing")),
+        Err(e) => Err(format!("Error deserializing: {}", e)),
     }
 }
 
 pub fn save(file_path: &str, schedule: &crate::schedule::Schedule) -> Result<(), std::io::Error> {
-    let mut file = File::create(file_path)?;
-    file.write_all(&serialize(schedule).into_bytes())?;
-    Ok(())
+    crate::write_atomically(file_path, serialize(schedule).as_bytes())
 }
 
 pub fn load(file_path: &str) -> Result<crate::schedule::Schedule, std::io::Error> {
@@ -91,7 +102,8 @@ pub fn load(file_path: &str) -> Result<crate::schedule::Schedule, std::io::Error
     match file.read_to_string(&mut file_content) {
         Ok(_) => {
             let mut schedule = crate::schedule::Schedule::new();
-            deserialize(file_content.as_str(), &mut schedule).unwrap();
+            deserialize(file_content.as_str(), &mut schedule)
+                .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidData, e))?;
             Ok(schedule)
         }
         Err(x) => Err(x),
@@ -106,7 +118,9 @@ fn test_serialization() -> Result<(), ()> {
         time: Local::now(),
         enabled_weekdays: vec![Weekday::Mon, Weekday::Tue],
         opened_time_servo1: 300,
-        opened_time_servo2: 300
+        opened_time_servo2: 300,
+        jiggle_servo1: None,
+        jiggle_servo2: None,
     });
 
     let json = serialize(&schedule);
@@ -119,6 +133,7 @@ fn test_serialization() -> Result<(), ()> {
 }
 
 #[test]
+#[allow(unused_parens)]
 fn test_save_load() -> Result<(), (std::io::Error)> {
     let mut schedule = crate::schedule::Schedule::new();
 
@@ -126,7 +141,9 @@ fn test_save_load() -> Result<(), (std::io::Error)> {
         time: Local::now(),
         enabled_weekdays: vec![Weekday::Mon, Weekday::Tue],
         opened_time_servo1: 300,
-        opened_time_servo2: 300
+        opened_time_servo2: 300,
+        jiggle_servo1: None,
+        jiggle_servo2: None,
     });
 
     save("test.json", &schedule)?;
@@ -135,3 +152,12 @@ fn test_save_load() -> Result<(), (std::io::Error)> {
     assert_eq!(1, loaded_schedule.get_times().len());
     Ok(())
 }
+
+#[test]
+fn test_deserialize_without_jiggle() {
+    let json = r#"[{"enabled":true,"time":"1970-01-01T07:30:00+01:00","enabled_weekdays":[1],"opened_time_servo1":320,"opened_time_servo2":280}]"#;
+    let mut schedule = crate::schedule::Schedule::new();
+
+    assert!(deserialize(json, &mut schedule).is_ok());
+    assert!(schedule.get_times()[0].jiggle_servo1.is_none());
+}
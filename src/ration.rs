@@ -0,0 +1,111 @@
+use chrono::prelude::*;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::prelude::*;
+
+use crate::config::{HopperConfig, OverBudget};
+
+#[derive(Debug, PartialEq)]
+pub enum Allowance {
+    Full(u64),
+    Capped(u64),
+    Refused,
+}
+
+/// Keeps track of how many milliseconds each hopper has been open today.
+#[derive(Serialize, Deserialize)]
+pub struct RationTracker {
+    date: String,
+    dispensed_ms: HashMap<String, u64>,
+}
+
+impl RationTracker {
+    pub fn new(today: NaiveDate) -> RationTracker {
+        RationTracker {
+            date: today.format("%Y-%m-%d").to_string(),
+            dispensed_ms: HashMap::new(),
+        }
+    }
+
+    /// Starts over from zero if the totals belong to an earlier day.
+    pub fn roll_over(&mut self, today: NaiveDate) {
+        let today = today.format("%Y-%m-%d").to_string();
+        if self.date != today {
+            self.date = today;
+            self.dispensed_ms.clear();
+        }
+    }
+
+    pub fn dispensed_today(&self, hopper: &str) -> u64 {
+        *self.dispensed_ms.get(hopper).unwrap_or(&0)
+    }
+
+    pub fn allowance(&self, hopper: &HopperConfig, requested_ms: u64) -> Allowance {
+        let max_ms = match hopper.daily_max {
+            Some(ration) => ration.max_ms(),
+            None => return Allowance::Full(requested_ms),
+        };
+        let remaining = max_ms.saturating_sub(self.dispensed_today(&hopper.name));
+
+        if requested_ms <= remaining {
+            Allowance::Full(requested_ms)
+        } else if remaining > 0 && hopper.over_budget == OverBudget::Cap {
+            Allowance::Capped(remaining)
+        } else {
+            Allowance::Refused
+        }
+    }
+
+    pub fn record(&mut self, hopper: &str, open_ms: u64) {
+        *self.dispensed_ms.entry(String::from(hopper)).or_insert(0) += open_ms;
+    }
+}
+
+pub fn load(file_path: &str) -> Result<RationTracker, std::io::Error> {
+    let mut file = File::open(file_path)?;
+    let mut file_content = String::new();
+    file.read_to_string(&mut file_content)?;
+    Ok(serde_json::from_str(&file_content)?)
+}
+
+#[cfg(test)]
+fn limited_hopper(max_ms: u64, over_budget: OverBudget) -> HopperConfig {
+    HopperConfig {
+        name: String::from("servo1"),
+        max_open_ms: 3000,
+        daily_max: Some(crate::config::DailyRation::Milliseconds(max_ms)),
+        over_budget,
+        motion: crate::motion::MotionProfile::default(),
+        jiggle: crate::jiggle::JigglePattern::default(),
+        retry: crate::config::RetryPolicy::default(),
+    }
+}
+
+#[test]
+fn allowance_capped() {
+    let hopper = limited_hopper(1000, OverBudget::Cap);
+    let mut tracker = RationTracker::new(NaiveDate::from_ymd(2019, 9, 1));
+    tracker.record("servo1", 800);
+
+    assert_eq!(Allowance::Capped(200), tracker.allowance(&hopper, 320));
+}
+
+#[test]
+fn allowance_refused() {
+    let hopper = limited_hopper(1000, OverBudget::Refuse);
+    let mut tracker = RationTracker::new(NaiveDate::from_ymd(2019, 9, 1));
+    tracker.record("servo1", 800);
+
+    assert_eq!(Allowance::Refused, tracker.allowance(&hopper, 320));
+}
+
+#[test]
+fn roll_over_resets_totals() {
+    let hopper = limited_hopper(1000, OverBudget::Refuse);
+    let mut tracker = RationTracker::new(NaiveDate::from_ymd(2019, 9, 1));
+    tracker.record("servo1", 1000);
+    tracker.roll_over(NaiveDate::from_ymd(2019, 9, 2));
+
+    assert_eq!(Allowance::Full(320), tracker.allowance(&hopper, 320));
+}
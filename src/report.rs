@@ -0,0 +1,118 @@
+use serde::{Deserialize, Serialize};
+use std::error::Error;
+use std::fmt;
+
+use crate::servo::PulseOutOfRange;
+use crate::signals::Terminated;
+
+/// Why a feed failed.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum FeedError {
+    /// The PWM could not be set up, the lid never moved.
+    HardwareUnavailable { reason: String },
+    /// A pulse width outside of the servo's limits was asked for.
+    PulseOutOfRange { pulse: u64, min: u64, max: u64 },
+    /// The PWM refused a command.
+    Actuator { reason: String },
+    /// A second termination signal cut the feed short.
+    Aborted,
+}
+
+impl FeedError {
+    /// Sorts out an error from moving the servo.
+    pub fn from_motion(error: Box<dyn Error>) -> FeedError {
+        if let Some(e) = error.downcast_ref::<PulseOutOfRange>() {
+            return FeedError::PulseOutOfRange {
+                pulse: e.pulse,
+                min: e.limits.min,
+                max: e.limits.max,
+            };
+        }
+        if error.is::<Terminated>() {
+            return FeedError::Aborted;
+        }
+        FeedError::Actuator {
+            reason: error.to_string(),
+        }
+    }
+}
+
+impl fmt::Display for FeedError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            FeedError::HardwareUnavailable { ref reason } => {
+                write!(f, "PWM unavailable: {}", reason)
+            }
+            FeedError::PulseOutOfRange { pulse, min, max } => write!(
+                f,
+                "Pulse width {} us is outside of {}-{} us",
+                pulse, min, max
+            ),
+            FeedError::Actuator { ref reason } => write!(f, "{}", reason),
+            FeedError::Aborted => write!(f, "Interrupted by a termination signal"),
+        }
+    }
+}
+
+impl Error for FeedError {}
+
+/// Something a sensor measured during a feed. picat has no sensors yet, so
+/// reports carry none so far.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct SensorReading {
+    pub sensor: String,
+    pub value: f64,
+}
+
+/// What happened during one feed from a hopper, for the history, alerts and
+/// anything else that wants to know.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct FeedReport {
+    pub hopper: String,
+    pub requested_ms: u64,
+    /// How long the lid was meant to stay open, after the daily ration had
+    /// its say.
+    pub open_ms: u64,
+    /// From the first lid movement until it was closed again.
+    pub elapsed_ms: u64,
+    pub jiggle_cycles: u32,
+    pub sensor_readings: Vec<SensorReading>,
+    pub error: Option<FeedError>,
+}
+
+impl FeedReport {
+    pub fn is_fed(&self) -> bool {
+        self.error.is_none()
+    }
+
+    /// A short summary like the status shows, "fed" or "failed: why".
+    pub fn outcome(&self) -> String {
+        match self.error {
+            None => String::from("fed"),
+            Some(ref e) => format!("failed: {}", e),
+        }
+    }
+}
+
+#[test]
+fn motion_errors_are_sorted_out() {
+    let limits = crate::servo::PulseLimits {
+        min: 1800,
+        max: 2700,
+    };
+    let out_of_range: Box<dyn Error> = Box::new(limits.check(3000).unwrap_err());
+    let aborted: Box<dyn Error> = Box::new(Terminated);
+    let refused: Box<dyn Error> = "PWM gone".into();
+
+    assert_eq!(
+        FeedError::PulseOutOfRange {
+            pulse: 3000,
+            min: 1800,
+            max: 2700
+        },
+        FeedError::from_motion(out_of_range)
+    );
+    assert_eq!(FeedError::Aborted, FeedError::from_motion(aborted));
+    assert_eq!("PWM gone", FeedError::from_motion(refused).to_string());
+}
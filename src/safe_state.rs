@@ -0,0 +1,87 @@
+use log::error;
+use std::time::Duration;
+
+use crate::servo::Servo;
+
+/// Puts every servo in its safe state, lid closed and PWM disabled, when
+/// dropped. Keep it alive for as long as the servos may move, so that neither
+/// an early return on error nor a panic can leave a lid open.
+pub struct SafeState<'s, 'a> {
+    servos: Vec<&'s Servo<'a>>,
+    travel: Duration,
+}
+
+impl<'s, 'a> SafeState<'s, 'a> {
+    pub fn new(servos: Vec<&'s Servo<'a>>, travel: Duration) -> SafeState<'s, 'a> {
+        SafeState { servos, travel }
+    }
+
+    /// Closes and disables the servos now.
+    pub fn engage(self) {}
+}
+
+impl<'s, 'a> Drop for SafeState<'s, 'a> {
+    fn drop(&mut self) {
+        for servo in self.servos.iter() {
+            if let Err(e) = servo.close_and_disable(self.travel) {
+                error!(error:% = e; "Failed to put servo in safe state");
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+fn test_servo<'a>(actuator: &'a crate::trace::TracingActuator<'a>) -> Servo<'a> {
+    let limits = crate::servo::PulseLimits {
+        min: 1800,
+        max: 2700,
+    };
+    Servo::new(limits, 2400, 1850, 2650, Default::default(), Some(actuator))
+}
+
+/// The pulses sent while enabled and whether the PWM ended up disabled.
+#[cfg(test)]
+fn commanded(trace: &crate::trace::Trace) -> (Vec<u64>, bool) {
+    let samples = trace.samples();
+    let pulses = samples
+        .iter()
+        .filter(|s| s.enabled)
+        .map(|s| s.pulse_us)
+        .collect();
+    (pulses, samples.last().is_some_and(|s| !s.enabled))
+}
+
+#[test]
+fn closes_on_error() {
+    let trace = crate::trace::Trace::new();
+    let clock = crate::clock::SystemClock;
+    let actuator = crate::trace::TracingActuator::new(&trace, &clock, rppal::pwm::Channel::Pwm0);
+    let servo = test_servo(&actuator);
+
+    let feed = || -> Result<(), Box<dyn std::error::Error>> {
+        let _safe_state = SafeState::new(vec![&servo], Duration::from_millis(0));
+        servo.move_to(servo.pulse_open)?;
+        servo.move_to(3000)?;
+        Ok(())
+    };
+
+    assert!(feed().is_err());
+    assert_eq!((vec![1850, 2400], true), commanded(&trace));
+}
+
+#[test]
+fn closes_on_panic() {
+    let trace = crate::trace::Trace::new();
+    let clock = crate::clock::SystemClock;
+    let actuator = crate::trace::TracingActuator::new(&trace, &clock, rppal::pwm::Channel::Pwm0);
+    let servo = test_servo(&actuator);
+
+    let result = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+        let _safe_state = SafeState::new(vec![&servo], Duration::from_millis(0));
+        servo.move_to(servo.pulse_open).unwrap();
+        panic!("lid stuck");
+    }));
+
+    assert!(result.is_err());
+    assert_eq!((vec![1850, 2400], true), commanded(&trace));
+}
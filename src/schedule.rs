@@ -1,16 +1,40 @@
 use chrono::prelude::*;
+
+use crate::jiggle::JigglePattern;
+
+#[derive(Clone, Default)]
 pub struct Schedule {
     times: Vec<Occasion>,
 }
 
+#[derive(Clone)]
 pub struct Occasion {
     pub time: DateTime<Local>,
     pub enabled_weekdays: Vec<Weekday>,
     pub opened_time_servo1: u64,
-    pub opened_time_servo2: u64
+    pub opened_time_servo2: u64,
+    pub jiggle_servo1: Option<JigglePattern>,
+    pub jiggle_servo2: Option<JigglePattern>,
 }
 
 impl Occasion {
+    /// Identifies the occasion by its time of day.
+    pub fn id(&self) -> String {
+        self.time.format("%H:%M").to_string()
+    }
+
+    /// Describes what is most likely a mistake about the occasion itself.
+    pub fn lint(&self) -> Vec<String> {
+        let mut problems = Vec::new();
+        if self.enabled_weekdays.is_empty() {
+            problems.push(format!("{} is not enabled on any weekday", self.id()));
+        }
+        if self.opened_time_servo1 == 0 {
+            problems.push(format!("{} never opens servo 1", self.id()));
+        }
+        problems
+    }
+
     pub fn is_enabled(&self, weekday: Weekday) -> bool {
         self.enabled_weekdays
             .iter()
@@ -32,23 +56,76 @@ impl Schedule {
     }
 
     pub fn contains(&self, time: DateTime<Local>) -> Option<&Occasion> {
-        for elem in self.times.iter() {
-            if elem.is_enabled(time.weekday())
+        self.times.iter().find(|elem| {
+            elem.is_enabled(time.weekday())
                 && elem.time.hour() == time.hour()
                 && elem.time.minute() == time.minute()
-            {
-                return Some(elem);
+        })
+    }
+
+    /// Returns when the first enabled occasion after `time` is due.
+    pub fn next_after(&self, time: DateTime<Local>) -> Option<DateTime<Local>> {
+        for days in 0..8 {
+            let date = time.date().naive_local() + chrono::Duration::days(days);
+            let next = self
+                .times
+                .iter()
+                .filter(|elem| elem.is_enabled(date.weekday()))
+                .filter_map(|elem| {
+                    let due = date.and_hms(elem.time.hour(), elem.time.minute(), 0);
+                    Local.from_local_datetime(&due).earliest()
+                })
+                .filter(|due| *due > time)
+                .min();
+            if next.is_some() {
+                return next;
             }
         }
         None
     }
 
+    /// Removes the occasion with id `id`. Returns false if there was none.
+    pub fn remove(&mut self, id: &str) -> bool {
+        let count = self.times.len();
+        self.times.retain(|elem| elem.id() != id);
+        self.times.len() != count
+    }
+
+    /// Puts `occasion` in place of the one with id `id`. Returns false if
+    /// there was none.
+    pub fn replace(&mut self, id: &str, occasion: Occasion) -> bool {
+        match self.times.iter_mut().find(|elem| elem.id() == id) {
+            Some(elem) => {
+                *elem = occasion;
+                true
+            }
+            None => false,
+        }
+    }
+
     pub fn get_times(&self) -> &Vec<Occasion> {
         &self.times
     }
+
+    /// Describes occasions that are most likely mistakes.
+    pub fn lint(&self) -> Vec<String> {
+        let mut problems = Vec::new();
+        for (index, elem) in self.times.iter().enumerate() {
+            let id = elem.id();
+            let earlier = self.times[..index].iter().filter(|o| o.id() == id).count();
+            if earlier == 1 {
+                let count = self.times.iter().filter(|o| o.id() == id).count();
+                problems.push(format!("{} is scheduled {} times", id, count));
+            }
+            problems.extend(elem.lint());
+        }
+        problems
+    }
 }
 
 #[test]
+#[allow(clippy::bool_assert_comparison)]
+#[rustfmt::skip]
 fn contains_true() {
     let mut schedule = Schedule::new();
     schedule.push(Occasion {
@@ -56,6 +133,8 @@ fn contains_true() {
         enabled_weekdays: vec![Weekday::Thu],
         opened_time_servo1: 300,
         opened_time_servo2: 300,
+        jiggle_servo1: None,
+        jiggle_servo2: None,
     });
 
     assert_eq!(
@@ -64,13 +143,17 @@ fn contains_true() {
 }
 
 #[test]
+#[allow(clippy::bool_assert_comparison)]
+#[rustfmt::skip]
 fn contains_false() {
     let mut schedule = Schedule::new();
     schedule.push(Occasion {
         time: Local.ymd(1970, 1, 1).and_hms(7, 30, 0),
         enabled_weekdays: vec![Weekday::Mon, Weekday::Tue],
         opened_time_servo1: 300,
-        opened_time_servo2: 300
+        opened_time_servo2: 300,
+        jiggle_servo1: None,
+        jiggle_servo2: None,
     });
 
     assert_eq!(
@@ -79,25 +162,124 @@ fn contains_false() {
 }
 
 #[test]
+#[allow(clippy::bool_assert_comparison)]
 fn occasion_enabled_true() {
     let occasion = Occasion {
         time: Local.ymd(1970, 1, 1).and_hms(7, 30, 0),
         enabled_weekdays: vec![Weekday::Mon],
         opened_time_servo1: 300,
-        opened_time_servo2: 300
+        opened_time_servo2: 300,
+        jiggle_servo1: None,
+        jiggle_servo2: None,
     };
 
     assert_eq!(occasion.is_enabled(Weekday::Mon), true);
 }
 
 #[test]
+#[allow(clippy::bool_assert_comparison)]
 fn occasion_enabled_false() {
     let occasion = Occasion {
         time: Local.ymd(1970, 1, 1).and_hms(7, 30, 0),
         enabled_weekdays: vec![Weekday::Mon],
         opened_time_servo1: 300,
-        opened_time_servo2: 300
+        opened_time_servo2: 300,
+        jiggle_servo1: None,
+        jiggle_servo2: None,
     };
 
     assert_eq!(occasion.is_enabled(Weekday::Tue), false);
-}
\ No newline at end of file
+}
+
+#[test]
+fn next_after_same_day() {
+    let mut schedule = Schedule::new();
+    for (hour, minute) in [(7, 30), (4, 25)].iter() {
+        schedule.push(Occasion {
+            time: Local.ymd(1970, 1, 1).and_hms(*hour, *minute, 0),
+            enabled_weekdays: vec![Weekday::Mon],
+            opened_time_servo1: 300,
+            opened_time_servo2: 300,
+            jiggle_servo1: None,
+            jiggle_servo2: None,
+        });
+    }
+
+    assert_eq!(
+        Some(Local.ymd(2019, 9, 2).and_hms(7, 30, 0)),
+        schedule.next_after(Local.ymd(2019, 9, 2).and_hms(5, 0, 0))
+    );
+}
+
+#[test]
+fn next_after_skips_disabled_days() {
+    let mut schedule = Schedule::new();
+    schedule.push(Occasion {
+        time: Local.ymd(1970, 1, 1).and_hms(7, 30, 0),
+        enabled_weekdays: vec![Weekday::Mon],
+        opened_time_servo1: 300,
+        opened_time_servo2: 300,
+        jiggle_servo1: None,
+        jiggle_servo2: None,
+    });
+
+    assert_eq!(
+        Some(Local.ymd(2019, 9, 9).and_hms(7, 30, 0)),
+        schedule.next_after(Local.ymd(2019, 9, 2).and_hms(7, 30, 0))
+    );
+}
+
+#[test]
+fn lint_finds_duplicates_and_dead_occasions() {
+    let mut schedule = Schedule::new();
+    for (hour, weekdays, opened) in [
+        (7, vec![Weekday::Mon], 300),
+        (7, vec![Weekday::Tue], 300),
+        (8, vec![], 300),
+        (7, vec![Weekday::Wed], 0),
+    ]
+    .iter()
+    {
+        schedule.push(Occasion {
+            time: Local.ymd(1970, 1, 1).and_hms(*hour, 30, 0),
+            enabled_weekdays: weekdays.clone(),
+            opened_time_servo1: *opened,
+            opened_time_servo2: 300,
+            jiggle_servo1: None,
+            jiggle_servo2: None,
+        });
+    }
+
+    assert_eq!(
+        vec![
+            "07:30 is scheduled 3 times",
+            "08:30 is not enabled on any weekday",
+            "07:30 never opens servo 1",
+        ],
+        schedule.lint()
+    );
+}
+
+#[test]
+fn occasions_are_replaced_and_removed_by_id() {
+    let mut schedule = Schedule::new();
+    for hour in [4, 7].iter() {
+        schedule.push(Occasion {
+            time: Local.ymd(1970, 1, 1).and_hms(*hour, 30, 0),
+            enabled_weekdays: vec![Weekday::Mon],
+            opened_time_servo1: 300,
+            opened_time_servo2: 300,
+            jiggle_servo1: None,
+            jiggle_servo2: None,
+        });
+    }
+    let mut later = schedule.get_times()[0].clone();
+    later.time = Local.ymd(1970, 1, 1).and_hms(5, 0, 0);
+
+    assert!(schedule.replace("04:30", later));
+    assert!(!schedule.replace("04:30", schedule.get_times()[1].clone()));
+    assert!(schedule.remove("07:30"));
+    assert!(!schedule.remove("07:30"));
+    let ids: Vec<String> = schedule.get_times().iter().map(Occasion::id).collect();
+    assert_eq!(vec!["05:00"], ids);
+}
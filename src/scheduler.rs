@@ -0,0 +1,904 @@
+use chrono::prelude::*;
+use log::{debug, error, info};
+use std::error::Error;
+use std::sync::{mpsc, Arc, Mutex};
+use std::thread;
+
+use crate::clock::{Clock, SystemClock};
+use crate::feeder::{Attempt, FeedOutcome};
+use crate::hardware::PwmHardware;
+use crate::report::FeedReport;
+use crate::{
+    actuation, alert, api, check, config, control, feeder, history, journal, lock, logging,
+    persistant_schedule_storage, schedule, sd_notify, signals, state, status, watchdog, Files,
+};
+
+pub fn create_default_schedule(schedule: &mut schedule::Schedule) {
+    schedule.push(schedule::Occasion {
+        time: Local.ymd(1970, 1, 1).and_hms(4, 25, 0),
+        enabled_weekdays: vec![
+            Weekday::Mon,
+            Weekday::Tue,
+            Weekday::Wed,
+            Weekday::Thu,
+            Weekday::Fri,
+            Weekday::Sat,
+            Weekday::Sun,
+        ],
+        opened_time_servo1: 320,
+        opened_time_servo2: 280,
+        jiggle_servo1: None,
+        jiggle_servo2: None,
+    });
+    schedule.push(schedule::Occasion {
+        time: Local.ymd(1970, 1, 1).and_hms(7, 30, 0),
+        enabled_weekdays: vec![
+            Weekday::Mon,
+            Weekday::Tue,
+            Weekday::Wed,
+            Weekday::Thu,
+            Weekday::Fri,
+            Weekday::Sat,
+            Weekday::Sun,
+        ],
+        opened_time_servo1: 320,
+        opened_time_servo2: 280,
+        jiggle_servo1: None,
+        jiggle_servo2: None,
+    });
+    schedule.push(schedule::Occasion {
+        time: Local.ymd(1970, 1, 1).and_hms(8, 30, 0),
+        enabled_weekdays: vec![
+            Weekday::Mon,
+            Weekday::Tue,
+            Weekday::Wed,
+            Weekday::Thu,
+            Weekday::Fri,
+            Weekday::Sat,
+            Weekday::Sun,
+        ],
+        opened_time_servo1: 320,
+        opened_time_servo2: 280,
+        jiggle_servo1: None,
+        jiggle_servo2: None,
+    });
+    schedule.push(schedule::Occasion {
+        time: Local.ymd(1970, 1, 1).and_hms(9, 30, 0),
+        enabled_weekdays: vec![
+            Weekday::Mon,
+            Weekday::Tue,
+            Weekday::Wed,
+            Weekday::Thu,
+            Weekday::Fri,
+            Weekday::Sat,
+            Weekday::Sun,
+        ],
+        opened_time_servo1: 320,
+        opened_time_servo2: 280,
+        jiggle_servo1: None,
+        jiggle_servo2: None,
+    });
+    schedule.push(schedule::Occasion {
+        time: Local.ymd(1970, 1, 1).and_hms(10, 30, 0),
+        enabled_weekdays: vec![
+            Weekday::Mon,
+            Weekday::Tue,
+            Weekday::Wed,
+            Weekday::Thu,
+            Weekday::Fri,
+            Weekday::Sat,
+            Weekday::Sun,
+        ],
+        opened_time_servo1: 320,
+        opened_time_servo2: 280,
+        jiggle_servo1: None,
+        jiggle_servo2: None,
+    });
+    schedule.push(schedule::Occasion {
+        time: Local.ymd(1970, 1, 1).and_hms(11, 30, 0),
+        enabled_weekdays: vec![
+            Weekday::Mon,
+            Weekday::Tue,
+            Weekday::Wed,
+            Weekday::Thu,
+            Weekday::Fri,
+            Weekday::Sat,
+            Weekday::Sun,
+        ],
+        opened_time_servo1: 320,
+        opened_time_servo2: 280,
+        jiggle_servo1: None,
+        jiggle_servo2: None,
+    });
+    schedule.push(schedule::Occasion {
+        time: Local.ymd(1970, 1, 1).and_hms(12, 30, 0),
+        enabled_weekdays: vec![
+            Weekday::Mon,
+            Weekday::Tue,
+            Weekday::Wed,
+            Weekday::Thu,
+            Weekday::Fri,
+            Weekday::Sat,
+            Weekday::Sun,
+        ],
+        opened_time_servo1: 320,
+        opened_time_servo2: 280,
+        jiggle_servo1: None,
+        jiggle_servo2: None,
+    });
+    schedule.push(schedule::Occasion {
+        time: Local.ymd(1970, 1, 1).and_hms(11, 30, 0),
+        enabled_weekdays: vec![
+            Weekday::Mon,
+            Weekday::Tue,
+            Weekday::Wed,
+            Weekday::Thu,
+            Weekday::Fri,
+            Weekday::Sat,
+            Weekday::Sun,
+        ],
+        opened_time_servo1: 320,
+        opened_time_servo2: 280,
+        jiggle_servo1: None,
+        jiggle_servo2: None,
+    });
+    schedule.push(schedule::Occasion {
+        time: Local.ymd(1970, 1, 1).and_hms(11, 30, 0),
+        enabled_weekdays: vec![
+            Weekday::Mon,
+            Weekday::Tue,
+            Weekday::Wed,
+            Weekday::Thu,
+            Weekday::Fri,
+            Weekday::Sat,
+            Weekday::Sun,
+        ],
+        opened_time_servo1: 320,
+        opened_time_servo2: 280,
+        jiggle_servo1: None,
+        jiggle_servo2: None,
+    });
+    schedule.push(schedule::Occasion {
+        time: Local.ymd(1970, 1, 1).and_hms(15, 30, 0),
+        enabled_weekdays: vec![
+            Weekday::Mon,
+            Weekday::Tue,
+            Weekday::Wed,
+            Weekday::Thu,
+            Weekday::Fri,
+            Weekday::Sat,
+            Weekday::Sun,
+        ],
+        opened_time_servo1: 320,
+        opened_time_servo2: 280,
+        jiggle_servo1: None,
+        jiggle_servo2: None,
+    });
+    schedule.push(schedule::Occasion {
+        time: Local.ymd(1970, 1, 1).and_hms(16, 30, 0),
+        enabled_weekdays: vec![
+            Weekday::Mon,
+            Weekday::Tue,
+            Weekday::Wed,
+            Weekday::Thu,
+            Weekday::Fri,
+            Weekday::Sat,
+            Weekday::Sun,
+        ],
+        opened_time_servo1: 320,
+        opened_time_servo2: 280,
+        jiggle_servo1: None,
+        jiggle_servo2: None,
+    });
+    schedule.push(schedule::Occasion {
+        time: Local.ymd(1970, 1, 1).and_hms(17, 30, 0),
+        enabled_weekdays: vec![
+            Weekday::Mon,
+            Weekday::Tue,
+            Weekday::Wed,
+            Weekday::Thu,
+            Weekday::Fri,
+            Weekday::Sat,
+            Weekday::Sun,
+        ],
+        opened_time_servo1: 320,
+        opened_time_servo2: 280,
+        jiggle_servo1: None,
+        jiggle_servo2: None,
+    });
+    schedule.push(schedule::Occasion {
+        time: Local.ymd(1970, 1, 1).and_hms(18, 30, 0),
+        enabled_weekdays: vec![
+            Weekday::Mon,
+            Weekday::Tue,
+            Weekday::Wed,
+            Weekday::Thu,
+            Weekday::Fri,
+            Weekday::Sat,
+            Weekday::Sun,
+        ],
+        opened_time_servo1: 320,
+        opened_time_servo2: 280,
+        jiggle_servo1: None,
+        jiggle_servo2: None,
+    });
+    schedule.push(schedule::Occasion {
+        time: Local.ymd(1970, 1, 1).and_hms(19, 30, 0),
+        enabled_weekdays: vec![
+            Weekday::Mon,
+            Weekday::Tue,
+            Weekday::Wed,
+            Weekday::Thu,
+            Weekday::Fri,
+            Weekday::Sat,
+            Weekday::Sun,
+        ],
+        opened_time_servo1: 320,
+        opened_time_servo2: 280,
+        jiggle_servo1: None,
+        jiggle_servo2: None,
+    });
+    schedule.push(schedule::Occasion {
+        time: Local.ymd(1970, 1, 1).and_hms(20, 30, 0),
+        enabled_weekdays: vec![
+            Weekday::Mon,
+            Weekday::Tue,
+            Weekday::Wed,
+            Weekday::Thu,
+            Weekday::Fri,
+            Weekday::Sat,
+            Weekday::Sun,
+        ],
+        opened_time_servo1: 320,
+        opened_time_servo2: 280,
+        jiggle_servo1: None,
+        jiggle_servo2: None,
+    });
+}
+
+/// Loads the config, creating the default one if there is none yet.
+pub fn load_config(files: &Files) -> Result<config::Config, std::io::Error> {
+    let config = match config::load(&files.config) {
+        Ok(x) => {
+            info!("Config found, using it");
+            x
+        }
+        Err(ref e) if e.kind() == std::io::ErrorKind::NotFound => {
+            info!("Config doesnt exist, creating new");
+            let config = config::Config::default();
+            if let Err(e) = config::save(&files.config, &config) {
+                error!(error:% = e; "Failed to persist config");
+            }
+            config
+        }
+        Err(e) => return Err(e),
+    };
+    if let Err(e) = logging::configure(&config.log) {
+        error!(error:% = e; "Failed to configure logging");
+    }
+    Ok(config)
+}
+
+/// Loads the schedule, creating the default one if there is none yet.
+pub fn load_schedule(files: &Files) -> Result<schedule::Schedule, std::io::Error> {
+    let mut schedule = schedule::Schedule::new();
+
+    match persistant_schedule_storage::load(&files.schedule) {
+        Ok(x) => {
+            info!("Persisted schedule found, using it");
+            schedule = x;
+        }
+        Err(ref e) if e.kind() == std::io::ErrorKind::NotFound => {
+            info!("Persisted schedule doesnt exist, creating new");
+            create_default_schedule(&mut schedule);
+            if let Err(e) = persistant_schedule_storage::save(&files.schedule, &schedule) {
+                error!(error:% = e; "Failed to persist schedule");
+            }
+        }
+        Err(e) => return Err(e),
+    }
+    Ok(schedule)
+}
+
+/// Reads the config and schedule again. When either can't be read or they
+/// don't pass the checks together, that is alerted about and both in use
+/// are kept, a slip while editing must not stop the feeding.
+pub fn reload(
+    files: &Files,
+    config: &mut config::Config,
+    schedule: &mut schedule::Schedule,
+) -> Result<(), String> {
+    info!("Reloading config and schedule");
+    let loaded = load_config(files)
+        .map_err(|e| format!("Failed to reload {}: {}", files.config, e))
+        .and_then(|config| {
+            load_schedule(files)
+                .map(|schedule| (config, schedule))
+                .map_err(|e| format!("Failed to reload {}: {}", files.schedule, e))
+        })
+        .and_then(|(config, schedule)| {
+            let problems = check::settings_problems(&schedule, &config);
+            if problems.is_empty() {
+                Ok((config, schedule))
+            } else {
+                Err(format!("Refusing to reload: {}", problems.join("; ")))
+            }
+        });
+    match loaded {
+        Ok((loaded_config, loaded_schedule)) => {
+            *config = loaded_config;
+            *schedule = loaded_schedule;
+            Ok(())
+        }
+        Err(e) => {
+            let message = format!("{}, keeping the config and schedule in use", e);
+            alert::raise(&message);
+            Err(message)
+        }
+    }
+}
+
+pub fn next_feed_status(schedule: &schedule::Schedule, now: DateTime<Local>) -> String {
+    match schedule.next_after(now) {
+        Some(t) => format!("Next feed at {}", t),
+        None => String::from("No feeds scheduled"),
+    }
+}
+
+/// What the feeder loop drives: the daemon, or a test or simulation
+/// standing in for it.
+pub trait Host {
+    fn schedule(&self) -> &schedule::Schedule;
+
+    /// Feeds an occasion that is due. Returns when to try again if the feed
+    /// failed and is worth retrying.
+    fn feed(
+        &mut self,
+        occasion: &schedule::Occasion,
+        attempt: Attempt,
+        clock: &dyn Clock,
+    ) -> Result<Option<DateTime<Local>>, Box<dyn Error>>;
+
+    /// Waits until `deadline`. Returns false when the feeder loop should stop.
+    fn idle(&mut self, deadline: DateTime<Local>, clock: &dyn Clock) -> bool;
+}
+
+/// Checks the schedule every 30 seconds and feeds each due occasion once,
+/// until the host says stop.
+pub fn run(
+    clock: &dyn Clock,
+    journal: &mut journal::Journal,
+    host: &mut dyn Host,
+) -> Result<(), Box<dyn Error>> {
+    loop {
+        let local = clock.now();
+
+        if let Some(occasion) = host.schedule().contains(local).cloned() {
+            let date = local.date().naive_local();
+            let id = occasion.id();
+            if journal.is_started(date, &id) {
+                info!(occasion = id.as_str(); "Occasion already dispensed, skipping");
+            } else {
+                if let Err(e) = journal.begin(date, &id, local) {
+                    error!(error:% = e; "Failed to write dispense journal");
+                }
+                let mut attempt = Attempt::first(local);
+                let mut result = host.feed(&occasion, attempt, clock);
+                let mut stopping = false;
+                while let Ok(Some(retry_at)) = result {
+                    info!(occasion = id.as_str(), attempt = attempt.number; "Trying again at {}", retry_at);
+                    if !host.idle(retry_at, clock) {
+                        stopping = true;
+                        break;
+                    }
+                    attempt = attempt.next();
+                    result = host.feed(&occasion, attempt, clock);
+                }
+                if let Err(e) = journal.complete(date, &id, clock.now()) {
+                    error!(error:% = e; "Failed to write dispense journal");
+                }
+                result?;
+                if stopping {
+                    break;
+                }
+            }
+
+            // make sure we never hit it the same minute
+            if !host.idle(clock.now() + chrono::Duration::seconds(60), clock) {
+                break;
+            }
+        }
+        debug!("Now {}", local);
+
+        if !host.idle(clock.now() + chrono::Duration::seconds(30), clock) {
+            break;
+        }
+    }
+    Ok(())
+}
+
+/// What the control socket and the HTTP API reach of the daemon: answers
+/// their requests and queues the feeds they ask for.
+pub struct Controls {
+    files: Files,
+    config: Arc<Mutex<config::Config>>,
+    schedule: schedule::Schedule,
+    status: Arc<Mutex<status::Status>>,
+    queue: actuation::Queue,
+    overrides: control::Overrides,
+}
+
+impl Controls {
+    /// Shares the config and status with the worker taking commands off
+    /// `queue`.
+    pub fn new(
+        files: Files,
+        config: Arc<Mutex<config::Config>>,
+        schedule: schedule::Schedule,
+        status: Arc<Mutex<status::Status>>,
+        queue: actuation::Queue,
+    ) -> Controls {
+        Controls {
+            files,
+            config,
+            schedule,
+            status,
+            queue,
+            overrides: control::Overrides::default(),
+        }
+    }
+
+    pub fn reload(&mut self) -> Result<(), String> {
+        reload(
+            &self.files,
+            &mut self.config.lock().unwrap(),
+            &mut self.schedule,
+        )
+    }
+
+    /// Answers a request that came in on the control socket or the HTTP
+    /// API. A feed is answered once the worker is done with it, without
+    /// holding up the feeder loop meanwhile.
+    pub fn answer(&mut self, call: control::Call, clock: &dyn Clock) {
+        use control::{Request, Response};
+
+        let now = clock.now();
+        let done = |message: &str| Response::Done {
+            message: String::from(message),
+        };
+        let response = match call.request.clone() {
+            Request::Status => Response::Status {
+                status: self.status.lock().unwrap().report(&self.schedule, now),
+                paused: self.overrides.paused,
+                skip_next: self.overrides.skip_next.map(|due| due.into()),
+            },
+            Request::FeedNow { ms } => return self.feed_now(call, ms, clock),
+            Request::SkipNext => match self.overrides.skip_next(&self.schedule, now) {
+                Some(due) => done(&format!("Skipping the feed at {}", due)),
+                None => Response::error("No feeds scheduled"),
+            },
+            Request::ResetBreaker => control::reset_breaker(&self.files),
+            Request::Queue => Response::Queue {
+                commands: self.queue.waiting(),
+            },
+            Request::Cancel { id } => {
+                if self.queue.cancel(id) {
+                    done(&format!("Cancelled command {}", id))
+                } else {
+                    Response::NotFound {
+                        message: format!("No command {} waiting", id),
+                    }
+                }
+            }
+            Request::Reload => match self.reload() {
+                Ok(()) => done("Reloaded config and schedule"),
+                Err(e) => Response::error(&e),
+            },
+            Request::Pause => {
+                self.overrides.paused = true;
+                done("Scheduled feeding paused")
+            }
+            Request::Resume => {
+                self.overrides.paused = false;
+                done("Scheduled feeding resumed")
+            }
+            request => control::answer_settings(
+                &request,
+                &self.files,
+                &mut self.schedule,
+                &mut self.config.lock().unwrap(),
+            ),
+        };
+        call.answer(response);
+    }
+
+    /// Queues a feed right away, with what the next scheduled feed would
+    /// give unless `ms` says otherwise. Not retried.
+    fn feed_now(&mut self, call: control::Call, ms: Option<u64>, clock: &dyn Clock) {
+        use control::Response;
+
+        if ms == Some(0) {
+            return call.answer(Response::error("Invalid feed: ms must be above 0"));
+        }
+        let now = clock.now();
+        let next = self
+            .schedule
+            .next_after(now)
+            .and_then(|due| self.schedule.contains(due));
+        let mut occasion = match (next, ms) {
+            (Some(next), _) => next.clone(),
+            (None, Some(_)) => schedule::Occasion {
+                time: now,
+                enabled_weekdays: Vec::new(),
+                opened_time_servo1: 0,
+                opened_time_servo2: 0,
+                jiggle_servo1: None,
+                jiggle_servo2: None,
+            },
+            (None, None) => {
+                return call.answer(Response::error(
+                    "No feeds scheduled to take the amount from",
+                ))
+            }
+        };
+        occasion.time = now;
+        occasion.enabled_weekdays = vec![now.weekday()];
+        if let Some(ms) = ms {
+            occasion.opened_time_servo1 = ms;
+        }
+        let problems = check::occasion_problems(&occasion, &self.config.lock().unwrap());
+        if !problems.is_empty() {
+            return call.answer(Response::error(&problems.join("; ")));
+        }
+
+        let ticket = self.queue.submit(actuation::Command::Feed {
+            occasion: Box::new(occasion),
+            trigger: history::Trigger::Manual,
+            attempt: Attempt::first(now),
+        });
+        thread::spawn(move || {
+            call.answer(match ticket.wait() {
+                actuation::Completion::Fed(FeedOutcome {
+                    report: Some(report),
+                    ..
+                }) => Response::Fed { report },
+                actuation::Completion::Fed(_) => {
+                    Response::error("Not fed, refused by the daily ration or the circuit breaker")
+                }
+                _ => Response::error("Feed was cancelled"),
+            })
+        });
+    }
+}
+
+/// The daemon, feeding through the actuation worker.
+struct Daemon {
+    controls: Controls,
+    notifier: sd_notify::Notifier,
+    heartbeat: watchdog::Heartbeat,
+    /// How often to beat while idle, when a hardware watchdog waits for it.
+    beat_interval: Option<std::time::Duration>,
+    calls: mpsc::Receiver<control::Call>,
+}
+
+impl Host for Daemon {
+    fn schedule(&self) -> &schedule::Schedule {
+        &self.controls.schedule
+    }
+
+    fn feed(
+        &mut self,
+        occasion: &schedule::Occasion,
+        attempt: Attempt,
+        clock: &dyn Clock,
+    ) -> Result<Option<DateTime<Local>>, Box<dyn Error>> {
+        if let Some(reason) = self.controls.overrides.hold(occasion, clock.now()) {
+            let trigger = history::Trigger::Scheduled;
+            let config = self.controls.config.lock().unwrap();
+            feeder::skip_occasion(
+                occasion,
+                trigger,
+                attempt,
+                &config,
+                &self.controls.files,
+                clock,
+                reason,
+            );
+            return Ok(None);
+        }
+        let ticket = self.controls.queue.submit(actuation::Command::Feed {
+            occasion: Box::new(occasion.clone()),
+            trigger: history::Trigger::Scheduled,
+            attempt,
+        });
+        match ticket.wait() {
+            actuation::Completion::Fed(outcome) => Ok(outcome.retry_at),
+            _ => {
+                info!(occasion = occasion.id().as_str(); "Feed was cancelled");
+                Ok(None)
+            }
+        }
+    }
+
+    /// Sleeps between schedule checks while handling signals and showing the
+    /// watchdogs that the loop is alive.
+    fn idle(&mut self, deadline: DateTime<Local>, clock: &dyn Clock) -> bool {
+        let next_feed = next_feed_status(&self.controls.schedule, clock.now());
+        let fault = self.controls.status.lock().unwrap().fault.clone();
+        self.notifier.status(&match fault {
+            Some(fault) => format!("DEGRADED, PWM unavailable: {}; {}", fault, next_feed),
+            None => next_feed,
+        });
+        loop {
+            self.notifier.watchdog();
+            self.heartbeat.beat();
+            while let Ok(call) = self.calls.try_recv() {
+                self.controls.answer(call, clock);
+            }
+            let now = clock.now();
+            if now >= deadline {
+                return true;
+            }
+            let wake = self
+                .notifier
+                .watchdog_interval()
+                .into_iter()
+                .chain(self.beat_interval)
+                .map(|interval| now + chrono::Duration::from_std(interval).unwrap())
+                .fold(deadline, DateTime::min);
+            match clock.sleep_until(wake) {
+                None | Some(signals::Event::Wake) => {}
+                Some(signals::Event::Shutdown) => return false,
+                Some(signals::Event::Reload) => {
+                    // already alerted about if it failed
+                    let _ = self.controls.reload();
+                }
+                Some(signals::Event::Status) => {
+                    info!(
+                        "Status: {}",
+                        self.controls
+                            .status
+                            .lock()
+                            .unwrap()
+                            .report(&self.controls.schedule, clock.now())
+                    );
+                }
+            }
+        }
+    }
+}
+
+/// Settles dispenses that were interrupted by a crash or power loss, so no
+/// occasion is fed twice because of a restart.
+fn reconcile_journal(
+    journal: &mut journal::Journal,
+    schedule: &schedule::Schedule,
+    config: &config::Config,
+    queue: &actuation::Queue,
+    clock: &dyn Clock,
+) {
+    let today = clock.now().date().naive_local();
+    for dispense in journal.incomplete() {
+        let occasion = schedule
+            .get_times()
+            .iter()
+            .find(|o| o.id() == dispense.occasion)
+            .filter(|_| dispense.date == today);
+        let refeed = match (config.incomplete_feed, occasion) {
+            (config::IncompleteFeed::Refeed, Some(occasion)) => Some(occasion),
+            _ => None,
+        };
+        alert::raise(&format!(
+            "Feed of {} on {} was interrupted, {}",
+            dispense.occasion,
+            dispense.date,
+            match refeed {
+                Some(_) => "feeding it again",
+                None => "assuming the cat was fed",
+            }
+        ));
+
+        // completed before refeeding, a second interruption must not feed a third time
+        if let Err(e) = journal.complete(dispense.date, &dispense.occasion, clock.now()) {
+            error!(error:% = e; "Failed to write dispense journal");
+            continue;
+        }
+        if let Some(occasion) = refeed {
+            // no retries, the daemon isn't up yet to keep the watchdogs happy meanwhile
+            let ticket = queue.submit(actuation::Command::Feed {
+                occasion: Box::new(occasion.clone()),
+                trigger: history::Trigger::Recovery,
+                attempt: Attempt::first(clock.now()),
+            });
+            if let actuation::Completion::Fed(FeedOutcome {
+                report: Some(FeedReport { error: Some(e), .. }),
+                ..
+            }) = ticket.wait()
+            {
+                error!(error:% = e; "Failed to feed interrupted occasion again");
+            }
+        }
+    }
+    if let Err(e) = journal.compact(today.pred()) {
+        error!(error:% = e; "Failed to compact dispense journal");
+    }
+}
+
+/// Starts the actuation worker on the PWM, the only thread that touches it.
+fn start_worker(worker: actuation::Worker, queue: &actuation::Queue) -> thread::JoinHandle<()> {
+    let queue = queue.clone();
+    thread::spawn(move || worker.run(&queue, &SystemClock, &PwmHardware))
+}
+
+pub fn main_feeder_loop() -> Result<(), Box<dyn Error>> {
+    let files = Files::default();
+    let _lock = lock::acquire(&files.lock)?;
+    let clock = SystemClock;
+    let config =
+        load_config(&files).map_err(|e| format!("Failed to read {}: {}", files.config, e))?;
+    let state = state::current(&files.state);
+    if let Some(t) = state.breaker.tripped_at() {
+        alert::raise(&format!(
+            "Circuit breaker tripped at {}, no feeding until reset with `picat reset-breaker`",
+            t
+        ));
+    }
+    let schedule =
+        load_schedule(&files).map_err(|e| format!("Failed to read {}: {}", files.schedule, e))?;
+    let problems = check::settings_problems(&schedule, &config);
+    if !problems.is_empty() {
+        return Err(format!("Refusing to feed: {}", problems.join("; ")).into());
+    }
+    let mut status = status::Status::new();
+    if let Some(fired) = state.last_fired() {
+        status.last_feed = Some(fired.time.with_timezone(&Local));
+        status.last_outcome = Some(fired.outcome.clone());
+    }
+    let mut journal = journal::Journal::open(&files.journal)?;
+
+    let shared_config = Arc::new(Mutex::new(config.clone()));
+    let status = Arc::new(Mutex::new(status));
+    let queue = actuation::Queue::new();
+    let worker = actuation::Worker {
+        files: files.clone(),
+        config: shared_config.clone(),
+        status: status.clone(),
+    };
+    let worker = start_worker(worker, &queue);
+    reconcile_journal(&mut journal, &schedule, &config, &queue, &clock);
+
+    let heartbeat = watchdog::Heartbeat::new();
+    let hardware_watchdog = match config.watchdog {
+        Some(ref watchdog_config) => match watchdog::start(watchdog_config, heartbeat.clone()) {
+            Ok(handle) => {
+                info!("Supervised by hardware watchdog {}", watchdog_config.device);
+                Some(handle)
+            }
+            Err(e) => {
+                alert::raise(&format!(
+                    "Failed to open hardware watchdog {}: {}",
+                    watchdog_config.device, e
+                ));
+                None
+            }
+        },
+        None => None,
+    };
+
+    let (calls, control_calls) = mpsc::channel();
+    if let Some(ref http) = config.http {
+        match api::serve(http, files.clone(), calls.clone()) {
+            Ok(address) => info!("Serving the HTTP API on {}", address),
+            Err(e) => error!(error:% = e; "Failed to serve the HTTP API on {}", http.address),
+        }
+    }
+    let listening = config
+        .control
+        .mode()
+        .map_err(Box::<dyn Error>::from)
+        .and_then(|mode| control::listen(&files.socket, mode, calls));
+    match listening {
+        Ok(()) => info!("Listening for control requests on {}", files.socket),
+        Err(e) => error!(error:% = e; "Failed to open control socket"),
+    }
+
+    let notifier = sd_notify::Notifier::from_env();
+    notifier.ready(&next_feed_status(&schedule, clock.now()));
+
+    let mut daemon = Daemon {
+        controls: Controls::new(
+            files.clone(),
+            shared_config,
+            schedule,
+            status,
+            queue.clone(),
+        ),
+        notifier,
+        heartbeat,
+        beat_interval: config.watchdog.as_ref().map(|w| w.beat_interval()),
+        calls: control_calls,
+    };
+    // A panic here ends the process without unwinding the worker, whose
+    // safe state would never close the lid, so it is caught until the lid
+    // is closed through the queue.
+    let result = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+        run(&clock, &mut journal, &mut daemon)
+    }));
+    // the lid is closed once a feed under way is done, and anyone still
+    // waiting for the hardware hears that it won't happen
+    let close = queue.submit(actuation::Command::Close);
+    queue.close();
+    let closed = close.wait() == actuation::Completion::Closed { error: None };
+    if worker.join().is_err() {
+        error!("Actuation worker failed");
+    }
+    if let Err(e) = std::fs::remove_file(&files.socket) {
+        debug!(error:% = e; "Failed to remove control socket");
+    }
+    let result = match result {
+        Ok(result) => result,
+        Err(panic) => {
+            error!(lid_closed = closed; "Feeder loop panicked");
+            std::panic::resume_unwind(panic)
+        }
+    };
+
+    // also when the loop failed, or the watchdogs take the stop for a hang
+    daemon.notifier.stopping();
+    if let Some(handle) = hardware_watchdog {
+        handle.stop();
+    }
+    if closed {
+        info!("Shutting down, lid is closed");
+    } else {
+        info!("Shutting down");
+    }
+    result
+}
+
+/// Feeds once for a second, like an unscheduled occasion, to see the lid
+/// work.
+pub fn test_servo_loop() -> Result<(), Box<dyn Error>> {
+    let files = Files::default();
+    let _lock = lock::acquire(&files.lock)?;
+    let config =
+        load_config(&files).map_err(|e| format!("Failed to read {}: {}", files.config, e))?;
+    let now = SystemClock.now();
+    let occasion = schedule::Occasion {
+        time: now,
+        enabled_weekdays: vec![now.weekday()],
+        opened_time_servo1: 1000,
+        opened_time_servo2: 0,
+        jiggle_servo1: None,
+        jiggle_servo2: None,
+    };
+    let queue = actuation::Queue::new();
+    let worker = actuation::Worker {
+        files,
+        config: Arc::new(Mutex::new(config)),
+        status: Arc::new(Mutex::new(status::Status::new())),
+    };
+    let worker = start_worker(worker, &queue);
+    let ticket = queue.submit(actuation::Command::Feed {
+        occasion: Box::new(occasion),
+        trigger: history::Trigger::Manual,
+        attempt: Attempt::first(now),
+    });
+    let completion = ticket.wait();
+    queue.close();
+    worker.join().map_err(|_| "Actuation worker failed")?;
+    match completion {
+        actuation::Completion::Fed(FeedOutcome {
+            report: Some(FeedReport { error: None, .. }),
+            ..
+        }) => Ok(()),
+        actuation::Completion::Fed(FeedOutcome {
+            report: Some(FeedReport { error: Some(e), .. }),
+            ..
+        }) => Err(e.into()),
+        actuation::Completion::Fed(_) => {
+            Err("Not fed, refused by the daily ration or the circuit breaker".into())
+        }
+        _ => Err("Feed was cancelled".into()),
+    }
+}
@@ -0,0 +1,124 @@
+use log::warn;
+use std::env;
+use std::os::linux::net::SocketAddrExt;
+use std::os::unix::net::{SocketAddr, UnixDatagram};
+use std::process;
+use std::time::Duration;
+
+/// Talks to systemd through the socket in `NOTIFY_SOCKET`. Does nothing when
+/// not started by systemd.
+pub struct Notifier {
+    target: Option<(UnixDatagram, SocketAddr)>,
+    watchdog_interval: Option<Duration>,
+}
+
+impl Notifier {
+    pub fn from_env() -> Notifier {
+        let target = env::var("NOTIFY_SOCKET").ok().and_then(|path| {
+            let address = match path.strip_prefix('@') {
+                Some(name) => SocketAddr::from_abstract_name(name.as_bytes()),
+                None => SocketAddr::from_pathname(&path),
+            };
+            Some((UnixDatagram::unbound().ok()?, address.ok()?))
+        });
+
+        let watchdog_for_us = match env::var("WATCHDOG_PID") {
+            Ok(pid) => pid.parse::<u32>().ok() == Some(process::id()),
+            Err(_) => true,
+        };
+        let watchdog_interval = env::var("WATCHDOG_USEC")
+            .ok()
+            .and_then(|usec| usec.parse::<u64>().ok())
+            .filter(|_| watchdog_for_us)
+            .map(|usec| Duration::from_micros(usec / 2));
+
+        Notifier {
+            target,
+            watchdog_interval,
+        }
+    }
+
+    /// Talks to the socket at `path` instead of the one systemd provides.
+    pub fn with_socket(
+        path: &std::path::Path,
+        watchdog_interval: Option<Duration>,
+    ) -> Result<Notifier, std::io::Error> {
+        Ok(Notifier {
+            target: Some((UnixDatagram::unbound()?, SocketAddr::from_pathname(path)?)),
+            watchdog_interval,
+        })
+    }
+
+    /// How often the watchdog has to be pinged, if systemd expects it at all.
+    pub fn watchdog_interval(&self) -> Option<Duration> {
+        self.watchdog_interval
+    }
+
+    pub fn ready(&self, status: &str) {
+        self.notify(&format!("READY=1\nSTATUS={}", status));
+    }
+
+    pub fn status(&self, status: &str) {
+        self.notify(&format!("STATUS={}", status));
+    }
+
+    pub fn watchdog(&self) {
+        if self.watchdog_interval.is_some() {
+            self.notify("WATCHDOG=1");
+        }
+    }
+
+    pub fn stopping(&self) {
+        self.notify("STOPPING=1");
+    }
+
+    fn notify(&self, message: &str) {
+        if let Some((ref socket, ref address)) = self.target {
+            if let Err(e) = socket.send_to_addr(message.as_bytes(), address) {
+                warn!(error:% = e; "Failed to notify systemd");
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+fn bind_test_socket(name: &str) -> (UnixDatagram, std::path::PathBuf) {
+    let path = env::temp_dir().join(format!("picat-{}-{}.sock", name, process::id()));
+    let _ = std::fs::remove_file(&path);
+    (UnixDatagram::bind(&path).unwrap(), path)
+}
+
+#[cfg(test)]
+fn receive(socket: &UnixDatagram) -> String {
+    let mut buf = [0; 256];
+    let len = socket.recv(&mut buf).unwrap();
+    String::from_utf8_lossy(&buf[..len]).into_owned()
+}
+
+#[test]
+fn sends_ready_and_status() {
+    let (socket, path) = bind_test_socket("ready");
+    let notifier = Notifier::with_socket(&path, None).unwrap();
+
+    notifier.ready("Next feed at 07:30");
+    notifier.status("Next feed at 08:30");
+
+    assert_eq!("READY=1\nSTATUS=Next feed at 07:30", receive(&socket));
+    assert_eq!("STATUS=Next feed at 08:30", receive(&socket));
+    std::fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn pings_watchdog_only_when_enabled() {
+    let (socket, path) = bind_test_socket("watchdog");
+    let without_watchdog = Notifier::with_socket(&path, None).unwrap();
+    let with_watchdog = Notifier::with_socket(&path, Some(Duration::from_secs(5))).unwrap();
+
+    without_watchdog.watchdog();
+    with_watchdog.watchdog();
+    without_watchdog.stopping();
+
+    assert_eq!("WATCHDOG=1", receive(&socket));
+    assert_eq!("STOPPING=1", receive(&socket));
+    std::fs::remove_file(&path).unwrap();
+}
@@ -1,7 +1,202 @@
 use rppal::pwm::Pwm;
+use std::cell::Cell;
+use std::error::Error;
+use std::fmt;
+use std::time::Duration;
+
+use crate::clock::{Clock, SystemClock};
+use crate::motion::MotionProfile;
+
+/// Something that can be commanded to a pulse width, normally a hardware `Pwm`.
+pub trait Actuator {
+    fn set_pulse_width(&self, pulse_width: Duration) -> Result<(), Box<dyn Error>>;
+    fn disable(&self) -> Result<(), Box<dyn Error>>;
+}
+
+impl Actuator for Pwm {
+    fn set_pulse_width(&self, pulse_width: Duration) -> Result<(), Box<dyn Error>> {
+        Pwm::set_pulse_width(self, pulse_width)?;
+        Ok(())
+    }
+
+    fn disable(&self) -> Result<(), Box<dyn Error>> {
+        Pwm::disable(self)?;
+        Ok(())
+    }
+}
+
+/// The mechanical range of a servo, pulses outside of it are never sent.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct PulseLimits {
+    pub min: u64,
+    pub max: u64,
+}
+
+impl PulseLimits {
+    pub fn check(&self, pulse: u64) -> Result<(), PulseOutOfRange> {
+        if pulse < self.min || pulse > self.max {
+            return Err(PulseOutOfRange {
+                pulse,
+                limits: *self,
+            });
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug)]
+pub struct PulseOutOfRange {
+    pub pulse: u64,
+    pub limits: PulseLimits,
+}
+
+impl fmt::Display for PulseOutOfRange {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "Pulse width {} us is outside of {}-{} us",
+            self.pulse, self.limits.min, self.limits.max
+        )
+    }
+}
+
+impl Error for PulseOutOfRange {}
+
 pub struct Servo<'a> {
+    pub limits: PulseLimits,
     pub pulse_closed: u64,
     pub pulse_open: u64,
     pub pulse_passed: u64,
-    pub pwm: Option<&'a Pwm>,
+    pub motion: MotionProfile,
+    pub pwm: Option<&'a dyn Actuator>,
+    clock: &'a dyn Clock,
+    position: Cell<u64>,
+}
+
+impl<'a> Servo<'a> {
+    /// Creates a servo that is assumed to rest at `pulse_closed`.
+    pub fn new(
+        limits: PulseLimits,
+        pulse_closed: u64,
+        pulse_open: u64,
+        pulse_passed: u64,
+        motion: MotionProfile,
+        pwm: Option<&'a dyn Actuator>,
+    ) -> Servo<'a> {
+        Servo {
+            limits,
+            pulse_closed,
+            pulse_open,
+            pulse_passed,
+            motion,
+            pwm,
+            clock: &SystemClock,
+            position: Cell::new(pulse_closed),
+        }
+    }
+
+    /// Times the servo's movements with `clock` instead of the system clock.
+    pub fn with_clock(mut self, clock: &'a dyn Clock) -> Servo<'a> {
+        self.clock = clock;
+        self
+    }
+
+    pub fn position(&self) -> u64 {
+        self.position.get()
+    }
+
+    /// Moves to `pulse` following the servo's motion profile. Fails without
+    /// moving if `pulse` is outside of the servo's limits.
+    pub fn move_to(&self, pulse: u64) -> Result<(), Box<dyn Error>> {
+        self.limits.check(pulse)?;
+        let pwm = match self.pwm {
+            Some(pwm) => pwm,
+            None => return Ok(()),
+        };
+
+        let steps = self.motion.steps(self.position.get(), pulse);
+        let last = steps.len() - 1;
+        for (i, step) in steps.into_iter().enumerate() {
+            self.limits.check(step)?;
+            pwm.set_pulse_width(Duration::from_micros(step))?;
+            self.position.set(step);
+            if i != last {
+                self.clock.wait(Duration::from_millis(self.motion.step_ms));
+            }
+        }
+        Ok(())
+    }
+
+    /// Commands the closed position right away, without following the motion
+    /// profile, waits `travel` for the lid to get there and disables the PWM.
+    /// The PWM is disabled even if closing fails.
+    pub fn close_and_disable(&self, travel: Duration) -> Result<(), Box<dyn Error>> {
+        let pwm = match self.pwm {
+            Some(pwm) => pwm,
+            None => return Ok(()),
+        };
+
+        self.limits.check(self.pulse_closed)?;
+        let closed = pwm.set_pulse_width(Duration::from_micros(self.pulse_closed));
+        if closed.is_ok() {
+            self.position.set(self.pulse_closed);
+            self.clock.wait(travel);
+        }
+        pwm.disable()?;
+        closed
+    }
+}
+
+#[cfg(test)]
+fn pulses(trace: &crate::trace::Trace) -> Vec<u64> {
+    trace.samples().iter().map(|s| s.pulse_us).collect()
+}
+
+#[test]
+fn move_follows_profile() {
+    use chrono::prelude::*;
+    let clock = crate::clock::VirtualClock::new(Local.ymd(2019, 9, 2).and_hms(4, 25, 0));
+    let trace = crate::trace::Trace::new();
+    let actuator = crate::trace::TracingActuator::new(&trace, &clock, rppal::pwm::Channel::Pwm0);
+    let profile = MotionProfile {
+        easing: crate::motion::Easing::Linear,
+        duration_ms: 60,
+        step_ms: 20,
+    };
+    let limits = PulseLimits {
+        min: 1800,
+        max: 2700,
+    };
+    let servo = Servo::new(limits, 2400, 1800, 2700, profile, Some(&actuator)).with_clock(&clock);
+
+    servo.move_to(1800).unwrap();
+    servo.move_to(2400).unwrap();
+
+    assert_eq!(vec![2200, 2000, 1800, 2000, 2200, 2400], pulses(&trace));
+    let samples = trace.samples();
+    assert_eq!(40, (samples[2].time - samples[0].time).num_milliseconds());
+}
+
+#[test]
+fn move_outside_limits_is_refused() {
+    let trace = crate::trace::Trace::new();
+    let actuator =
+        crate::trace::TracingActuator::new(&trace, &SystemClock, rppal::pwm::Channel::Pwm0);
+    let limits = PulseLimits {
+        min: 1800,
+        max: 2700,
+    };
+    let servo = Servo::new(
+        limits,
+        2400,
+        1850,
+        2650,
+        Default::default(),
+        Some(&actuator),
+    );
+
+    assert!(servo.move_to(2800).is_err());
+    assert!(servo.move_to(1000).is_err());
+    assert!(pulses(&trace).is_empty());
+    assert_eq!(2400, servo.position());
 }
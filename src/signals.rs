@@ -0,0 +1,122 @@
+use signal_hook::consts::{SIGHUP, SIGINT, SIGTERM, SIGUSR1};
+use signal_hook::iterator::Signals;
+use std::error::Error;
+use std::fmt;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::OnceLock;
+use std::thread;
+use std::time::{Duration, Instant};
+
+const SLEEP_SLICE_MS: u64 = 50;
+
+#[derive(Default)]
+struct Flags {
+    shutdown: AtomicBool,
+    abort: AtomicBool,
+    reload: AtomicBool,
+    status: AtomicBool,
+    wake: AtomicBool,
+}
+
+static FLAGS: OnceLock<Flags> = OnceLock::new();
+
+fn flags() -> &'static Flags {
+    FLAGS.get_or_init(Flags::default)
+}
+
+#[derive(Debug)]
+pub struct Terminated;
+
+impl fmt::Display for Terminated {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Interrupted by a termination signal")
+    }
+}
+
+impl Error for Terminated {}
+
+#[derive(Debug, PartialEq)]
+pub enum Event {
+    Shutdown,
+    Reload,
+    Status,
+    /// Not a signal, a control request is waiting for the feeder loop.
+    Wake,
+}
+
+/// Starts handling signals in the background instead of letting them kill
+/// the process.
+///
+/// The first SIGTERM or SIGINT asks for a graceful shutdown, letting an
+/// in-progress dispense finish. A second one aborts the dispense, leaving it
+/// to the safe state to close the lid. SIGHUP asks for a reload and SIGUSR1
+/// for a status report.
+pub fn register() -> Result<(), std::io::Error> {
+    let mut signals = Signals::new([SIGTERM, SIGINT, SIGHUP, SIGUSR1])?;
+    thread::spawn(move || {
+        for signal in signals.forever() {
+            let flags = flags();
+            match signal {
+                SIGHUP => flags.reload.store(true, Ordering::SeqCst),
+                SIGUSR1 => flags.status.store(true, Ordering::SeqCst),
+                _ => {
+                    if flags.shutdown.swap(true, Ordering::SeqCst) {
+                        flags.abort.store(true, Ordering::SeqCst);
+                    }
+                }
+            }
+        }
+    });
+    Ok(())
+}
+
+/// Ends a `wait` in any thread early with `Event::Wake`.
+pub fn wake() {
+    flags().wake.store(true, Ordering::SeqCst);
+}
+
+fn abort_requested() -> bool {
+    flags().abort.load(Ordering::SeqCst)
+}
+
+/// Sleeps for `duration` while the servos move, returning early only if the
+/// dispense has to be aborted.
+pub fn sleep(duration: Duration) -> Result<(), Terminated> {
+    let deadline = Instant::now() + duration;
+    loop {
+        if abort_requested() {
+            return Err(Terminated);
+        }
+        let now = Instant::now();
+        if now >= deadline {
+            return Ok(());
+        }
+        thread::sleep((deadline - now).min(Duration::from_millis(SLEEP_SLICE_MS)));
+    }
+}
+
+/// Sleeps for at most `duration`, returning early with the first signal that
+/// needs handling.
+pub fn wait(duration: Duration) -> Option<Event> {
+    let deadline = Instant::now() + duration;
+    let flags = flags();
+    loop {
+        if flags.shutdown.load(Ordering::SeqCst) {
+            return Some(Event::Shutdown);
+        }
+        if flags.reload.swap(false, Ordering::SeqCst) {
+            return Some(Event::Reload);
+        }
+        if flags.status.swap(false, Ordering::SeqCst) {
+            return Some(Event::Status);
+        }
+        if flags.wake.swap(false, Ordering::SeqCst) {
+            return Some(Event::Wake);
+        }
+        let now = Instant::now();
+        if now >= deadline {
+            return None;
+        }
+        thread::sleep((deadline - now).min(Duration::from_millis(SLEEP_SLICE_MS)));
+    }
+}
@@ -0,0 +1,230 @@
+//! Runs the real scheduler, storage and feeding code against a virtual clock
+//! and servos that only exist in a trace, so schedule changes can be reviewed
+//! before they reach the Pi.
+
+use chrono::prelude::*;
+use log::warn;
+use std::env;
+use std::error::Error;
+use std::fs;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use crate::clock::{Clock, VirtualClock};
+use crate::config::Config;
+use crate::feeder::{Attempt, Feeder};
+use crate::history::{self, parse_date};
+use crate::journal::Journal;
+use crate::schedule::{Occasion, Schedule};
+use crate::scheduler::{self, Host};
+use crate::signals::Event;
+use crate::status::Status;
+use crate::trace::{Sample, Trace, TracingHardware};
+use crate::Files;
+
+pub struct Options {
+    pub from: NaiveDate,
+    pub days: u32,
+    /// How many times faster than real time to run, as fast as possible if
+    /// not given.
+    pub speed: Option<f64>,
+}
+
+impl Options {
+    /// Parses `--from YYYY-MM-DD [--days N] [--speed Nx]`.
+    pub fn parse(args: &[String]) -> Result<Options, String> {
+        let mut from = None;
+        let mut days = 7;
+        let mut speed = None;
+        let mut args = args.iter();
+        while let Some(arg) = args.next() {
+            let value = args
+                .next()
+                .ok_or_else(|| format!("Missing value for {}", arg))?;
+            match arg.as_str() {
+                "--from" => from = Some(parse_date(value)?),
+                "--days" => {
+                    days = value
+                        .parse()
+                        .map_err(|_| format!("Invalid number of days {}", value))?
+                }
+                "--speed" => speed = Some(parse_speed(value)?),
+                _ => return Err(format!("Unknown option {}", arg)),
+            }
+        }
+        Ok(Options {
+            from: from.ok_or("Missing --from")?,
+            days,
+            speed,
+        })
+    }
+}
+
+fn parse_speed(value: &str) -> Result<f64, String> {
+    match value.trim_end_matches('x').parse::<f64>() {
+        Ok(speed) if speed > 0.0 => Ok(speed),
+        _ => Err(format!(
+            "Invalid speed {}, expected something like 1000x",
+            value
+        )),
+    }
+}
+
+/// Stands in for the daemon, feeding into a trace until the simulated time is
+/// up.
+struct Simulation<'a> {
+    config: Config,
+    files: Files,
+    schedule: Schedule,
+    status: Status,
+    until: DateTime<Local>,
+    hardware: TracingHardware<'a>,
+}
+
+impl<'a> Host for Simulation<'a> {
+    fn schedule(&self) -> &Schedule {
+        &self.schedule
+    }
+
+    fn feed(
+        &mut self,
+        occasion: &Occasion,
+        attempt: Attempt,
+        clock: &dyn Clock,
+    ) -> Result<Option<DateTime<Local>>, Box<dyn Error>> {
+        let feeder = Feeder {
+            config: &self.config,
+            files: &self.files,
+            clock,
+            hardware: &self.hardware,
+        };
+        let trigger = history::Trigger::Scheduled;
+        let outcome = feeder.feed_occasion(occasion, trigger, attempt, &mut self.status);
+        Ok(outcome.retry_at)
+    }
+
+    fn idle(&mut self, deadline: DateTime<Local>, clock: &dyn Clock) -> bool {
+        if deadline >= self.until {
+            return false;
+        }
+        clock.sleep_until(deadline) != Some(Event::Shutdown)
+    }
+}
+
+/// A directory of its own for each simulation, removed however it ends.
+struct ScratchDir(PathBuf);
+
+impl ScratchDir {
+    fn create() -> Result<ScratchDir, std::io::Error> {
+        static COUNT: AtomicUsize = AtomicUsize::new(0);
+        let name = format!(
+            "picat-simulate-{}-{}",
+            std::process::id(),
+            COUNT.fetch_add(1, Ordering::SeqCst)
+        );
+        let dir = env::temp_dir().join(name);
+        fs::create_dir_all(&dir)?;
+        Ok(ScratchDir(dir))
+    }
+}
+
+impl Drop for ScratchDir {
+    fn drop(&mut self) {
+        if let Err(e) = fs::remove_dir_all(&self.0) {
+            warn!(error:% = e; "Failed to remove {}", self.0.display());
+        }
+    }
+}
+
+fn midnight(date: NaiveDate) -> Result<DateTime<Local>, String> {
+    Local
+        .from_local_date(&date)
+        .earliest()
+        .map(|date| date.and_hms(0, 0, 0))
+        .ok_or_else(|| format!("{} does not exist in the local timezone", date))
+}
+
+/// Simulates the days asked for and returns the timeline of feeds and servo
+/// commands. Keeps its files in a scratch directory, which leaves the state,
+/// history and journal of the real feeder alone. A termination signal ends
+/// a paced simulation early with the timeline so far.
+pub fn run(
+    options: &Options,
+    schedule: Schedule,
+    config: Config,
+) -> Result<String, Box<dyn Error>> {
+    let start = midnight(options.from)?;
+    let until = midnight(options.from + chrono::Duration::days(i64::from(options.days)))?;
+
+    let scratch_dir = ScratchDir::create()?;
+
+    let clock = match options.speed {
+        Some(speed) => VirtualClock::with_speed(start, speed),
+        None => VirtualClock::new(start),
+    };
+    let trace = Trace::new();
+    let simulation = Simulation {
+        config,
+        files: Files::in_dir(&scratch_dir.0),
+        schedule,
+        status: Status::new(),
+        until,
+        hardware: TracingHardware {
+            trace: &trace,
+            clock: &clock,
+        },
+    };
+    let entries = feed(simulation, &clock)?;
+    Ok(timeline(&entries, &trace.samples()))
+}
+
+/// Runs the scheduler through the simulation and returns the feeding history
+/// it left behind.
+fn feed(
+    mut simulation: Simulation,
+    clock: &dyn Clock,
+) -> Result<Vec<history::Entry>, Box<dyn Error>> {
+    let files = simulation.files.clone();
+    let mut journal = Journal::open(&files.journal)?;
+    scheduler::run(clock, &mut journal, &mut simulation)?;
+    match history::load(&files.history) {
+        Ok(entries) => Ok(entries),
+        Err(ref e) if e.kind() == std::io::ErrorKind::NotFound => Ok(Vec::new()),
+        Err(e) => Err(e.into()),
+    }
+}
+
+/// Lists feeds and servo commands in the order they happened.
+pub fn timeline(entries: &[history::Entry], samples: &[Sample]) -> String {
+    let mut lines: Vec<(DateTime<FixedOffset>, String)> = entries
+        .iter()
+        .map(|entry| {
+            let mut line = format!(
+                "feed  {} {}, requested {} ms, opened {} ms: {}",
+                entry.occasion,
+                entry.hopper,
+                entry.requested_ms,
+                entry.open_ms,
+                entry.outcome.as_str()
+            );
+            if let Some(ref error) = entry.error {
+                line.push_str(&format!(" ({})", error));
+            }
+            (entry.time, line)
+        })
+        .collect();
+    lines.extend(samples.iter().map(|sample| {
+        let line = if sample.enabled {
+            format!("pwm{}  {} us", sample.channel, sample.pulse_us)
+        } else {
+            format!("pwm{}  off", sample.channel)
+        };
+        (sample.time.into(), line)
+    }));
+    // stable, so a feed comes before the servo commands of the same instant
+    lines.sort_by_key(|&(time, _)| time);
+    lines
+        .into_iter()
+        .map(|(time, line)| format!("{}  {}\n", time.format("%Y-%m-%d %H:%M:%S%.3f"), line))
+        .collect()
+}
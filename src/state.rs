@@ -0,0 +1,212 @@
+use chrono::prelude::*;
+use log::{error, info};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fs::{self, File};
+use std::io::prelude::*;
+use std::path::Path;
+use std::sync::{Mutex, MutexGuard};
+
+use crate::alert;
+use crate::breaker::CircuitBreaker;
+use crate::ration::RationTracker;
+use crate::{LEGACY_BREAKER_FILE_NAME, LEGACY_RATION_FILE_NAME};
+
+/// When an occasion last fired and how it went.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct Fired {
+    pub time: DateTime<FixedOffset>,
+    pub outcome: String,
+}
+
+/// Things that are currently broken.
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq)]
+pub struct Faults {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub hardware: Option<String>,
+}
+
+/// What picat has learned while running and needs to remember across
+/// restarts. Kept apart from the schedule and the config, which are only
+/// written by humans.
+#[derive(Serialize, Deserialize)]
+#[serde(default)]
+pub struct State {
+    pub last_fired: HashMap<String, Fired>,
+    pub rations: RationTracker,
+    pub breaker: CircuitBreaker,
+    pub faults: Faults,
+}
+
+impl Default for State {
+    fn default() -> Self {
+        State {
+            last_fired: HashMap::new(),
+            rations: RationTracker::new(Local::today().naive_local()),
+            breaker: CircuitBreaker::new(),
+            faults: Faults::default(),
+        }
+    }
+}
+
+impl State {
+    pub fn fired(&mut self, occasion: &str, time: DateTime<Local>, outcome: &str) {
+        self.last_fired.insert(
+            String::from(occasion),
+            Fired {
+                time: time.into(),
+                outcome: String::from(outcome),
+            },
+        );
+    }
+
+    /// The most recent firing of any occasion.
+    pub fn last_fired(&self) -> Option<&Fired> {
+        self.last_fired.values().max_by_key(|fired| fired.time)
+    }
+}
+
+pub fn save(file_path: &str, state: &State) -> Result<(), std::io::Error> {
+    crate::write_atomically(file_path, &serde_json::to_vec_pretty(state)?)
+}
+
+pub fn load(file_path: &str) -> Result<State, std::io::Error> {
+    let mut file = File::open(file_path)?;
+    let mut file_content = String::new();
+    file.read_to_string(&mut file_content)?;
+    Ok(serde_json::from_str(&file_content)?)
+}
+
+/// Loads the state file at `file_path`, starting from the files of earlier
+/// versions next to it if there is none yet.
+pub fn current(file_path: &str) -> State {
+    match load(file_path) {
+        Ok(x) => x,
+        Err(ref e) if e.kind() == std::io::ErrorKind::NotFound => {
+            let legacy = |name: &str| Path::new(file_path).with_file_name(name);
+            let mut state = State::default();
+            let ration_path = legacy(LEGACY_RATION_FILE_NAME);
+            if let Ok(rations) = crate::ration::load(&ration_path.to_string_lossy()) {
+                info!("Moving {} into the state file", LEGACY_RATION_FILE_NAME);
+                state.rations = rations;
+            }
+            let breaker_path = legacy(LEGACY_BREAKER_FILE_NAME);
+            if let Ok(breaker) = crate::breaker::load(&breaker_path.to_string_lossy()) {
+                info!("Moving {} into the state file", LEGACY_BREAKER_FILE_NAME);
+                state.breaker = breaker;
+            }
+            state
+        }
+        Err(e) => unreadable(file_path, &e),
+    }
+}
+
+/// Starting over from an empty state would forget a tripped breaker and the
+/// daily totals. The unreadable file is moved aside for a human to look at
+/// instead, and the state starts over with the breaker tripped.
+fn unreadable(file_path: &str, error: &std::io::Error) -> State {
+    let now = Local::now();
+    let aside = format!("{}.unreadable-{}", file_path, now.format("%Y%m%dT%H%M%S"));
+    let mut state = State::default();
+    state.breaker.trip(now);
+    let moved = fs::rename(file_path, &aside);
+    alert::raise(&format!(
+        "Failed to read {} ({}), {}, no feeding until reset with `picat reset-breaker`",
+        file_path,
+        error,
+        match moved {
+            Ok(()) => format!("moved it to {}", aside),
+            Err(ref e) => format!("failed to move it aside: {}", e),
+        }
+    ));
+    if moved.is_ok() {
+        if let Err(e) = save(file_path, &state) {
+            error!(error:% = e; "Failed to persist state");
+        }
+    }
+    state
+}
+
+/// Held while the state is read, changed and written back, so the daemon's
+/// threads don't undo each other's changes.
+static UPDATING: Mutex<()> = Mutex::new(());
+
+fn updating() -> MutexGuard<'static, ()> {
+    UPDATING.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+/// Lets `f` change the runtime state in `file_path` and persists the change.
+/// Carries on with the changed state if it can't be saved.
+pub fn update<R>(file_path: &str, f: impl FnOnce(&mut State) -> R) -> R {
+    let _updating = updating();
+    let mut state = current(file_path);
+    let result = f(&mut state);
+    if let Err(e) = save(file_path, &state) {
+        error!(error:% = e; "Failed to persist state");
+    }
+    result
+}
+
+/// Resets the circuit breaker in `file_path` and returns when it tripped, if
+/// it had.
+pub fn reset_breaker(file_path: &str) -> Result<Option<DateTime<Local>>, std::io::Error> {
+    let _updating = updating();
+    let mut state = current(file_path);
+    let tripped_at = state.breaker.tripped_at();
+    state.breaker.reset();
+    save(file_path, &state)?;
+    Ok(tripped_at)
+}
+
+#[test]
+fn round_trips_atomically() {
+    let path = std::env::temp_dir().join(format!("picat-state-{}.json", std::process::id()));
+    let path = path.to_str().unwrap();
+    let mut state = State::default();
+    state.fired("04:25", Local.ymd(2019, 9, 1).and_hms(4, 25, 0), "fed");
+    state.fired(
+        "07:30",
+        Local.ymd(2019, 9, 1).and_hms(7, 30, 0),
+        "failed: lid stuck",
+    );
+    state.rations.record("servo1", 320);
+    state.faults.hardware = Some(String::from("PWM unavailable"));
+
+    save(path, &state).unwrap();
+    let state = load(path).unwrap();
+
+    assert_eq!(320, state.rations.dispensed_today("servo1"));
+    assert_eq!("failed: lid stuck", state.last_fired().unwrap().outcome);
+    assert_eq!(Some(String::from("PWM unavailable")), state.faults.hardware);
+    assert!(!std::path::Path::new(&format!("{}.tmp", path)).exists());
+    fs::remove_file(path).unwrap();
+}
+
+#[test]
+fn missing_sections_get_defaults() {
+    let state: State = serde_json::from_str("{\"faults\":{\"hardware\":\"gone\"}}").unwrap();
+
+    assert!(state.last_fired().is_none());
+    assert!(!state.breaker.is_tripped());
+    assert_eq!(0, state.rations.dispensed_today("servo1"));
+}
+
+#[test]
+fn unreadable_state_is_moved_aside_and_trips_the_breaker() {
+    let dir = std::env::temp_dir().join(format!("picat-state-bad-{}", std::process::id()));
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(&dir).unwrap();
+    let path = dir.join("state.json");
+    let path = path.to_str().unwrap();
+    fs::write(path, "{\"breaker\": {\"tripped_at\": 15").unwrap();
+
+    assert!(current(path).breaker.is_tripped());
+    assert!(load(path).unwrap().breaker.is_tripped());
+    let aside: Vec<_> = fs::read_dir(&dir)
+        .unwrap()
+        .map(|entry| entry.unwrap().file_name().into_string().unwrap())
+        .filter(|name| name.starts_with("state.json.unreadable-"))
+        .collect();
+    assert_eq!(1, aside.len());
+    fs::remove_dir_all(&dir).unwrap();
+}
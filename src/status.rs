@@ -0,0 +1,45 @@
+use chrono::prelude::*;
+
+use crate::schedule::Schedule;
+
+/// What the feeder loop knows about how things are going.
+#[derive(Clone)]
+pub struct Status {
+    pub last_feed: Option<DateTime<Local>>,
+    pub last_outcome: Option<String>,
+    pub hardware: String,
+    /// Why the PWM is unavailable, feeding is degraded while it is.
+    pub fault: Option<String>,
+}
+
+impl Default for Status {
+    fn default() -> Self {
+        Status::new()
+    }
+}
+
+impl Status {
+    pub fn new() -> Status {
+        Status {
+            last_feed: None,
+            last_outcome: None,
+            hardware: String::from("not initialized yet"),
+            fault: None,
+        }
+    }
+
+    pub fn report(&self, schedule: &Schedule, now: DateTime<Local>) -> String {
+        let next_feed = match schedule.next_after(now) {
+            Some(t) => t.to_string(),
+            None => String::from("none scheduled"),
+        };
+        let last_feed = match (self.last_feed, &self.last_outcome) {
+            (Some(t), Some(outcome)) => format!("{} ({})", t, outcome),
+            _ => String::from("none since start"),
+        };
+        format!(
+            "next feed: {}, last feed: {}, hardware: {}",
+            next_feed, last_feed, self.hardware
+        )
+    }
+}
@@ -0,0 +1,107 @@
+use chrono::prelude::*;
+use rppal::pwm::Channel;
+use std::cell::{Cell, RefCell};
+use std::error::Error;
+use std::time::Duration;
+
+use crate::clock::Clock;
+use crate::hardware::Hardware;
+use crate::servo::{Actuator, PulseLimits};
+
+/// One command sent to a servo.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Sample {
+    pub time: DateTime<Local>,
+    pub channel: u8,
+    pub pulse_us: u64,
+    pub enabled: bool,
+}
+
+/// Everything the servos were told to do, in order.
+#[derive(Default)]
+pub struct Trace {
+    samples: RefCell<Vec<Sample>>,
+}
+
+impl Trace {
+    pub fn new() -> Trace {
+        Trace::default()
+    }
+
+    pub fn samples(&self) -> Vec<Sample> {
+        self.samples.borrow().clone()
+    }
+
+    fn record(&self, sample: Sample) {
+        self.samples.borrow_mut().push(sample);
+    }
+}
+
+fn channel_number(channel: Channel) -> u8 {
+    match channel {
+        Channel::Pwm0 => 0,
+        Channel::Pwm1 => 1,
+    }
+}
+
+/// An actuator that records what it is told into a trace, timed by a clock.
+pub struct TracingActuator<'a> {
+    trace: &'a Trace,
+    clock: &'a dyn Clock,
+    channel: u8,
+    pulse_us: Cell<u64>,
+}
+
+impl<'a> TracingActuator<'a> {
+    pub fn new(trace: &'a Trace, clock: &'a dyn Clock, channel: Channel) -> TracingActuator<'a> {
+        TracingActuator {
+            trace,
+            clock,
+            channel: channel_number(channel),
+            pulse_us: Cell::new(0),
+        }
+    }
+}
+
+impl<'a> Actuator for TracingActuator<'a> {
+    fn set_pulse_width(&self, pulse_width: Duration) -> Result<(), Box<dyn Error>> {
+        self.pulse_us.set(pulse_width.as_micros() as u64);
+        self.trace.record(Sample {
+            time: self.clock.now(),
+            channel: self.channel,
+            pulse_us: self.pulse_us.get(),
+            enabled: true,
+        });
+        Ok(())
+    }
+
+    fn disable(&self) -> Result<(), Box<dyn Error>> {
+        self.trace.record(Sample {
+            time: self.clock.now(),
+            channel: self.channel,
+            pulse_us: self.pulse_us.get(),
+            enabled: false,
+        });
+        Ok(())
+    }
+}
+
+/// Hardware whose servos only exist in a trace.
+pub struct TracingHardware<'a> {
+    pub trace: &'a Trace,
+    pub clock: &'a dyn Clock,
+}
+
+impl<'a> Hardware for TracingHardware<'a> {
+    fn actuator(
+        &self,
+        channel: Channel,
+        pulse_closed: u64,
+        limits: PulseLimits,
+    ) -> Result<Box<dyn Actuator + '_>, Box<dyn Error>> {
+        limits.check(pulse_closed)?;
+        let actuator = TracingActuator::new(self.trace, self.clock, channel);
+        actuator.set_pulse_width(Duration::from_micros(pulse_closed))?;
+        Ok(Box::new(actuator))
+    }
+}
@@ -0,0 +1,230 @@
+use chrono::prelude::*;
+use log::error;
+use serde::{Deserialize, Serialize};
+use std::fs::{File, OpenOptions};
+use std::io::prelude::*;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// The wall clock is not trusted before this year, a Pi without network
+/// starts out in 1970.
+const MIN_SANE_YEAR: i32 = 2019;
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct WatchdogConfig {
+    #[serde(default = "default_device")]
+    pub device: String,
+    #[serde(default = "default_pet_interval_seconds")]
+    pub pet_interval_seconds: u64,
+    #[serde(default = "default_max_heartbeat_age_seconds")]
+    pub max_heartbeat_age_seconds: u64,
+}
+
+fn default_device() -> String {
+    String::from("/dev/watchdog")
+}
+
+fn default_pet_interval_seconds() -> u64 {
+    5
+}
+
+fn default_max_heartbeat_age_seconds() -> u64 {
+    150
+}
+
+impl WatchdogConfig {
+    /// How long the scheduler loop may go without a heartbeat before the
+    /// watchdog lets the system reboot.
+    pub fn max_heartbeat_age(&self) -> Duration {
+        Duration::from_secs(self.max_heartbeat_age_seconds)
+    }
+
+    /// How often the scheduler loop beats at least, well within the age.
+    pub fn beat_interval(&self) -> Duration {
+        self.max_heartbeat_age() / 3
+    }
+}
+
+/// Shows that the scheduler loop is still making progress.
+#[derive(Clone)]
+pub struct Heartbeat(Arc<Mutex<Instant>>);
+
+impl Default for Heartbeat {
+    fn default() -> Self {
+        Heartbeat::new()
+    }
+}
+
+impl Heartbeat {
+    pub fn new() -> Heartbeat {
+        Heartbeat(Arc::new(Mutex::new(Instant::now())))
+    }
+
+    pub fn beat(&self) {
+        *self.0.lock().unwrap() = Instant::now();
+    }
+
+    pub fn age(&self) -> Duration {
+        self.0.lock().unwrap().elapsed()
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub enum Health {
+    Healthy,
+    SchedulerStalled(Duration),
+    ClockInsane(DateTime<Local>),
+}
+
+/// Keeps the hardware watchdog from rebooting the system for as long as the
+/// scheduler is healthy.
+pub struct Supervisor {
+    device: File,
+    heartbeat: Heartbeat,
+    max_heartbeat_age: Duration,
+}
+
+impl Supervisor {
+    /// Opens the watchdog device, which arms it.
+    pub fn open(
+        config: &WatchdogConfig,
+        heartbeat: Heartbeat,
+    ) -> Result<Supervisor, std::io::Error> {
+        Ok(Supervisor {
+            device: OpenOptions::new().write(true).open(&config.device)?,
+            heartbeat,
+            max_heartbeat_age: config.max_heartbeat_age(),
+        })
+    }
+
+    pub fn health(&self, now: DateTime<Local>) -> Health {
+        let age = self.heartbeat.age();
+        if age > self.max_heartbeat_age {
+            Health::SchedulerStalled(age)
+        } else if now.year() < MIN_SANE_YEAR {
+            Health::ClockInsane(now)
+        } else {
+            Health::Healthy
+        }
+    }
+
+    /// Pets the watchdog if everything is healthy.
+    pub fn check(&mut self, now: DateTime<Local>) -> Result<Health, std::io::Error> {
+        let health = self.health(now);
+        if health == Health::Healthy {
+            self.device.write_all(b"1")?;
+            self.device.flush()?;
+        }
+        Ok(health)
+    }
+
+    /// Disarms the watchdog, for when picat stops on purpose.
+    pub fn close(mut self) -> Result<(), std::io::Error> {
+        self.device.write_all(b"V")?;
+        self.device.flush()
+    }
+}
+
+pub struct Handle {
+    stop: Arc<AtomicBool>,
+    thread: thread::JoinHandle<()>,
+}
+
+impl Handle {
+    /// Stops petting and disarms the watchdog.
+    pub fn stop(self) {
+        self.stop.store(true, Ordering::SeqCst);
+        if self.thread.join().is_err() {
+            error!("Watchdog thread panicked");
+        }
+    }
+}
+
+/// Opens the watchdog device and pets it from a background thread.
+pub fn start(config: &WatchdogConfig, heartbeat: Heartbeat) -> Result<Handle, std::io::Error> {
+    let mut supervisor = Supervisor::open(config, heartbeat)?;
+    let interval = Duration::from_secs(config.pet_interval_seconds);
+    let stop = Arc::new(AtomicBool::new(false));
+    let thread_stop = Arc::clone(&stop);
+
+    let thread = thread::spawn(move || {
+        let mut last_health = Health::Healthy;
+        while !thread_stop.load(Ordering::SeqCst) {
+            match supervisor.check(Local::now()) {
+                Ok(health) => {
+                    if health != Health::Healthy && last_health == Health::Healthy {
+                        crate::alert::raise(&format!(
+                            "Not petting the hardware watchdog, system will reboot: {:?}",
+                            health
+                        ));
+                    }
+                    last_health = health;
+                }
+                Err(e) => error!(error:% = e; "Failed to pet the hardware watchdog"),
+            }
+            thread::sleep(interval);
+        }
+        if let Err(e) = supervisor.close() {
+            error!(error:% = e; "Failed to disarm the hardware watchdog");
+        }
+    });
+
+    Ok(Handle { stop, thread })
+}
+
+#[cfg(test)]
+fn fake_device(name: &str) -> WatchdogConfig {
+    let path = std::env::temp_dir().join(format!("picat-{}-{}", name, std::process::id()));
+    File::create(&path).unwrap();
+    WatchdogConfig {
+        device: path.to_string_lossy().into_owned(),
+        pet_interval_seconds: 1,
+        max_heartbeat_age_seconds: 60,
+    }
+}
+
+#[test]
+fn pets_while_healthy_and_disarms_on_close() {
+    let config = fake_device("watchdog-healthy");
+    let mut supervisor = Supervisor::open(&config, Heartbeat::new()).unwrap();
+
+    let now = Local.ymd(2019, 9, 1).and_hms(7, 30, 0);
+    assert_eq!(Health::Healthy, supervisor.check(now).unwrap());
+    assert_eq!(Health::Healthy, supervisor.check(now).unwrap());
+    supervisor.close().unwrap();
+
+    assert_eq!("11V", std::fs::read_to_string(&config.device).unwrap());
+    std::fs::remove_file(&config.device).unwrap();
+}
+
+#[test]
+fn does_not_pet_with_insane_clock() {
+    let config = fake_device("watchdog-clock");
+    let mut supervisor = Supervisor::open(&config, Heartbeat::new()).unwrap();
+
+    let now = Local.ymd(1970, 1, 1).and_hms(0, 1, 0);
+    assert_eq!(Health::ClockInsane(now), supervisor.check(now).unwrap());
+
+    assert_eq!("", std::fs::read_to_string(&config.device).unwrap());
+    std::fs::remove_file(&config.device).unwrap();
+}
+
+#[test]
+fn does_not_pet_when_scheduler_stalls() {
+    let mut config = fake_device("watchdog-stalled");
+    config.max_heartbeat_age_seconds = 0;
+    let heartbeat = Heartbeat::new();
+    let mut supervisor = Supervisor::open(&config, heartbeat.clone()).unwrap();
+    thread::sleep(Duration::from_millis(10));
+
+    let now = Local.ymd(2019, 9, 1).and_hms(7, 30, 0);
+    match supervisor.check(now).unwrap() {
+        Health::SchedulerStalled(_) => {}
+        health => panic!("unexpected {:?}", health),
+    }
+
+    assert_eq!("", std::fs::read_to_string(&config.device).unwrap());
+    std::fs::remove_file(&config.device).unwrap();
+}
@@ -0,0 +1,25 @@
+mod common;
+
+use std::process::Command;
+
+use common::in_scratch_dir;
+use picat::SCHEDULE_FILE_NAME;
+
+#[test]
+fn malformed_schedule_is_reported_as_a_problem() {
+    let output = in_scratch_dir("check", |dir| {
+        std::fs::write(dir.join(SCHEDULE_FILE_NAME), "[{\"time\": \"07:").unwrap();
+        Command::new(env!("CARGO_BIN_EXE_picat-check"))
+            .current_dir(dir)
+            .output()
+            .unwrap()
+    });
+
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert_eq!(Some(1), output.status.code());
+    assert!(
+        stdout.contains(&format!("{}: Error deserializing", SCHEDULE_FILE_NAME)),
+        "{}",
+        stdout
+    );
+}
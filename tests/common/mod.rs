@@ -0,0 +1,98 @@
+//! What the integration tests share: occasions to schedule, a stand-in for
+//! the daemon and directories of their own to keep files in.
+
+#![allow(dead_code)]
+
+use chrono::prelude::*;
+use std::error::Error;
+use std::path::{Path, PathBuf};
+
+use picat::clock::Clock;
+use picat::feeder::Attempt;
+use picat::schedule::{Occasion, Schedule};
+use picat::scheduler::Host;
+
+pub fn occasion(hour: u32, minute: u32, weekdays: Vec<Weekday>) -> Occasion {
+    Occasion {
+        time: Local.ymd(1970, 1, 1).and_hms(hour, minute, 0),
+        enabled_weekdays: weekdays,
+        opened_time_servo1: 320,
+        opened_time_servo2: 280,
+        jiggle_servo1: None,
+        jiggle_servo2: None,
+    }
+}
+
+pub fn every_day() -> Vec<Weekday> {
+    vec![
+        Weekday::Mon,
+        Weekday::Tue,
+        Weekday::Wed,
+        Weekday::Thu,
+        Weekday::Fri,
+        Weekday::Sat,
+        Weekday::Sun,
+    ]
+}
+
+/// Stands in for the daemon until `until`, handing every feed to `feed`
+/// and sleeping on the clock in between.
+pub struct StandIn<F> {
+    pub schedule: Schedule,
+    pub until: DateTime<Local>,
+    feed: F,
+}
+
+pub fn stand_in<F>(schedule: Schedule, until: DateTime<Local>, feed: F) -> StandIn<F>
+where
+    F: FnMut(&Occasion, Attempt, &dyn Clock) -> Option<DateTime<Local>>,
+{
+    StandIn {
+        schedule,
+        until,
+        feed,
+    }
+}
+
+impl<F> Host for StandIn<F>
+where
+    F: FnMut(&Occasion, Attempt, &dyn Clock) -> Option<DateTime<Local>>,
+{
+    fn schedule(&self) -> &Schedule {
+        &self.schedule
+    }
+
+    fn feed(
+        &mut self,
+        occasion: &Occasion,
+        attempt: Attempt,
+        clock: &dyn Clock,
+    ) -> Result<Option<DateTime<Local>>, Box<dyn Error>> {
+        Ok((self.feed)(occasion, attempt, clock))
+    }
+
+    fn idle(&mut self, deadline: DateTime<Local>, clock: &dyn Clock) -> bool {
+        if deadline >= self.until {
+            return false;
+        }
+        clock.sleep_until(deadline);
+        true
+    }
+}
+
+/// A path for `name` under the temporary directory, apart from other test
+/// runs.
+pub fn scratch_path(name: &str) -> PathBuf {
+    std::env::temp_dir().join(format!("picat-{}-{}", name, std::process::id()))
+}
+
+/// Runs `f` with a directory of its own, out of the source tree, removed
+/// afterwards even when `f` panics.
+pub fn in_scratch_dir<R>(name: &str, f: impl FnOnce(&Path) -> R) -> R {
+    let dir = scratch_path(name);
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(&dir).unwrap();
+    let result = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| f(&dir)));
+    std::fs::remove_dir_all(&dir).unwrap();
+    result.unwrap_or_else(|e| std::panic::resume_unwind(e))
+}
@@ -0,0 +1,84 @@
+//! Talks to the control socket the way `picatctl` does, with a stand-in for
+//! the feeder loop answering the requests.
+
+mod common;
+
+use std::fs;
+use std::io::prelude::*;
+use std::io::BufReader;
+use std::os::unix::fs::PermissionsExt;
+use std::os::unix::net::UnixStream;
+use std::sync::mpsc;
+use std::thread;
+
+use common::scratch_path;
+use picat::control::{self, Request, Response};
+
+/// Listens on a socket of its own, answering every request with a
+/// description of it.
+fn echo_socket(name: &str, mode: u32) -> String {
+    let path = scratch_path(&format!("control-{}", name));
+    let path = path.to_str().unwrap().to_string();
+    let (calls, received) = mpsc::channel::<control::Call>();
+    control::listen(&path, mode, calls).unwrap();
+    thread::spawn(move || {
+        for call in received {
+            let message = format!("{:?}", call.request);
+            call.answer(Response::Done { message });
+        }
+    });
+    path
+}
+
+#[test]
+fn requests_are_answered_by_the_feeder_loop() {
+    let path = echo_socket("echo", 0o660);
+
+    let response = control::send(&path, &Request::FeedNow { ms: Some(500) }).unwrap();
+
+    assert_eq!(
+        Response::Done {
+            message: String::from("FeedNow { ms: Some(500) }")
+        },
+        response
+    );
+    fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn socket_gets_the_configured_mode() {
+    let path = echo_socket("mode", 0o600);
+
+    let mode = fs::metadata(&path).unwrap().permissions().mode();
+
+    assert_eq!(0o600, mode & 0o777);
+    fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn one_connection_carries_several_requests_and_survives_bad_ones() {
+    let path = echo_socket("lines", 0o660);
+    let mut stream = UnixStream::connect(&path).unwrap();
+    let mut reader = BufReader::new(stream.try_clone().unwrap());
+
+    writeln!(stream, "{{\"command\":\"launch\"}}").unwrap();
+    writeln!(stream, "{{\"command\":\"status\"}}").unwrap();
+    let mut lines = Vec::new();
+    for _ in 0..2 {
+        let mut line = String::new();
+        reader.read_line(&mut line).unwrap();
+        lines.push(serde_json::from_str::<Response>(&line).unwrap());
+    }
+
+    match lines[0] {
+        Response::Error { ref message } => assert!(message.starts_with("Invalid request")),
+        ref other => panic!("expected an error, got {:?}", other),
+    }
+    assert_eq!(
+        Response::Done {
+            message: String::from("Status")
+        },
+        lines[1]
+    );
+    fs::remove_file(&path).unwrap();
+}
@@ -0,0 +1,364 @@
+//! Runs the scheduler and the real feeding code against hardware that fails
+//! on purpose, to check that the lid ends up closed, the failure is recorded
+//! and feeding carries on.
+
+mod common;
+
+use chrono::prelude::*;
+use std::time::Duration;
+
+use common::{in_scratch_dir, occasion, stand_in};
+use picat::clock::{Clock, VirtualClock};
+use picat::config::Config;
+use picat::fault::{FaultPlan, FaultyHardware};
+use picat::feeder::{guarded_feed, note_hardware, FeedRequest, Feeder, HardwareRetry};
+use picat::hardware::{
+    probe_hardware, PULSE_CLOSED_US, PULSE_OPEN_US, PULSE_PASSED_US, SERVO1_LIMITS,
+};
+use picat::history::{self, Outcome};
+use picat::journal::Journal;
+use picat::report::FeedError;
+use picat::schedule::Schedule;
+use picat::scheduler;
+use picat::servo::Servo;
+use picat::status::Status;
+use picat::trace::{Sample, Trace};
+use picat::Files;
+
+struct Run {
+    entries: Vec<history::Entry>,
+    samples: Vec<Sample>,
+    status: Status,
+}
+
+/// Runs `f` with the files the feeding code writes in a directory of its
+/// own, out of the source tree.
+fn with_files<R>(name: &str, f: impl FnOnce(Files) -> R) -> R {
+    in_scratch_dir(&format!("faults-{}", name), |dir| f(Files::in_dir(dir)))
+}
+
+/// A config that never retries, to see each fault once.
+fn without_retries() -> Config {
+    let mut config = Config::default();
+    config.hoppers[0].retry.attempts = 1;
+    config
+}
+
+/// Feeds 04:25 and 07:30 on a Monday from hardware that fails as planned.
+fn run(name: &str, plan: FaultPlan) -> Run {
+    run_with(name, plan, without_retries())
+}
+
+fn run_with(name: &str, plan: FaultPlan, config: Config) -> Run {
+    with_files(name, |files| {
+        let clock = VirtualClock::new(Local.ymd(2019, 9, 2).and_hms(0, 0, 0));
+        let trace = Trace::new();
+        let mut schedule = Schedule::new();
+        schedule.push(occasion(4, 25, vec![Weekday::Mon]));
+        schedule.push(occasion(7, 30, vec![Weekday::Mon]));
+        let hardware = FaultyHardware::new(&trace, &clock, plan);
+        let mut status = Status::new();
+        let until = Local.ymd(2019, 9, 3).and_hms(0, 0, 0);
+        let mut host = stand_in(schedule, until, |occasion, attempt, clock: &dyn Clock| {
+            let feeder = Feeder {
+                config: &config,
+                files: &files,
+                clock,
+                hardware: &hardware,
+            };
+            let trigger = history::Trigger::Scheduled;
+            feeder
+                .feed_occasion(occasion, trigger, attempt, &mut status)
+                .retry_at
+        });
+        let mut journal = Journal::open(&files.journal).unwrap();
+        scheduler::run(&clock, &mut journal, &mut host).unwrap();
+        Run {
+            entries: history::load(&files.history).unwrap_or_default(),
+            samples: trace.samples(),
+            status,
+        }
+    })
+}
+
+fn outcomes(entries: &[history::Entry]) -> Vec<(String, Outcome)> {
+    entries
+        .iter()
+        .map(|entry| (entry.occasion.clone(), entry.outcome))
+        .collect()
+}
+
+/// The last pulse commanded before the feed at `hour` ended.
+fn closing_pulse(samples: &[Sample], hour: u32) -> u64 {
+    samples
+        .iter()
+        .rfind(|sample| sample.time.hour() == hour && sample.enabled)
+        .expect("no pulses")
+        .pulse_us
+}
+
+#[test]
+fn failed_pulse_closes_lid_and_is_recorded() {
+    // the first feed creates the actuator, opens and then fails to jiggle
+    let run = run(
+        "pulse",
+        FaultPlan {
+            fail_pulse: Some(3),
+            ..FaultPlan::default()
+        },
+    );
+
+    assert_eq!(
+        vec![
+            (String::from("04:25"), Outcome::Failed),
+            (String::from("07:30"), Outcome::Fed),
+        ],
+        outcomes(&run.entries)
+    );
+    assert_eq!(
+        Some(String::from("Injected fault: pulse command failed")),
+        run.entries[0].error
+    );
+    assert_eq!(PULSE_CLOSED_US, closing_pulse(&run.samples, 4));
+    assert_eq!(PULSE_CLOSED_US, closing_pulse(&run.samples, 7));
+    assert_eq!(2, run.samples.iter().filter(|s| !s.enabled).count());
+    assert_eq!(Some(String::from("fed")), run.status.last_outcome);
+}
+
+#[test]
+fn failed_disable_leaves_lid_closed_and_feeding_on() {
+    let run = run(
+        "disable",
+        FaultPlan {
+            fail_disable: true,
+            ..FaultPlan::default()
+        },
+    );
+
+    assert_eq!(
+        vec![
+            (String::from("04:25"), Outcome::Fed),
+            (String::from("07:30"), Outcome::Fed),
+        ],
+        outcomes(&run.entries)
+    );
+    assert_eq!(PULSE_CLOSED_US, closing_pulse(&run.samples, 4));
+    assert_eq!(PULSE_CLOSED_US, closing_pulse(&run.samples, 7));
+    assert!(run.samples.iter().all(|s| s.enabled));
+}
+
+#[test]
+fn failed_construction_does_not_stop_the_scheduler() {
+    let run = run(
+        "create",
+        FaultPlan {
+            fail_creates: 1,
+            ..FaultPlan::default()
+        },
+    );
+
+    assert_eq!(
+        vec![
+            (String::from("04:25"), Outcome::Failed),
+            (String::from("07:30"), Outcome::Fed),
+        ],
+        outcomes(&run.entries)
+    );
+    assert_eq!(0, run.entries[0].open_ms);
+    assert_eq!(
+        Some(String::from(
+            "PWM unavailable: Injected fault: PWM unavailable"
+        )),
+        run.entries[0].error
+    );
+    assert!(run.samples.iter().all(|s| s.time.hour() == 7));
+    assert_eq!(PULSE_CLOSED_US, closing_pulse(&run.samples, 7));
+    assert_eq!("ok", run.status.hardware);
+}
+
+#[test]
+fn hanging_actuator_delays_but_does_not_stop_feeding() {
+    let run = run(
+        "hang",
+        FaultPlan {
+            hang_pulse: Some((2, Duration::from_secs(90))),
+            ..FaultPlan::default()
+        },
+    );
+
+    assert_eq!(
+        vec![
+            (String::from("04:25"), Outcome::Fed),
+            (String::from("07:30"), Outcome::Fed),
+        ],
+        outcomes(&run.entries)
+    );
+    let opened = &run.samples[1];
+    assert_eq!(Local.ymd(2019, 9, 2).and_hms(4, 26, 30), opened.time);
+    assert_eq!(PULSE_CLOSED_US, closing_pulse(&run.samples, 4));
+}
+
+#[test]
+fn degraded_hardware_is_probed_with_backoff_until_it_recovers() {
+    with_files("retry", |files| {
+        let start = Local.ymd(2019, 9, 2).and_hms(0, 0, 0);
+        let clock = VirtualClock::new(start);
+        let trace = Trace::new();
+        let plan = FaultPlan {
+            fail_creates: 3,
+            ..FaultPlan::default()
+        };
+        let hardware = FaultyHardware::new(&trace, &clock, plan);
+        let mut status = Status::new();
+        let mut retry = HardwareRetry::new();
+
+        note_hardware(&files, &mut status, probe_hardware(&hardware));
+        assert_eq!(
+            "degraded, PWM unavailable: Injected fault: PWM unavailable",
+            status.hardware
+        );
+        let mut probes = Vec::new();
+        while let Some(due) = retry.next_probe(&status, clock.now()) {
+            probes.push((due - start).num_seconds());
+            clock.sleep_until(due);
+            retry.probe_if_due(&files, &mut status, &hardware, clock.now());
+        }
+
+        assert_eq!(vec![10, 30, 70], probes);
+        assert_eq!(None, status.fault);
+        assert_eq!("ok", status.hardware);
+        assert_eq!(None, picat::state::current(&files.state).faults.hardware);
+    })
+}
+
+#[test]
+fn failed_feed_is_retried_and_each_attempt_recorded() {
+    let run = run_with(
+        "retry-once",
+        FaultPlan {
+            fail_pulse: Some(3),
+            ..FaultPlan::default()
+        },
+        Config::default(),
+    );
+
+    assert_eq!(
+        vec![
+            (String::from("04:25"), Outcome::Failed),
+            (String::from("04:25"), Outcome::Fed),
+            (String::from("07:30"), Outcome::Fed),
+        ],
+        outcomes(&run.entries)
+    );
+    let attempts: Vec<u32> = run.entries.iter().map(|e| e.attempt).collect();
+    assert_eq!(vec![1, 2, 1], attempts);
+    // a minute after the first try failed, in whole seconds
+    assert_eq!(Local.ymd(2019, 9, 2).and_hms(4, 26, 0), run.entries[1].time);
+}
+
+#[test]
+fn persistent_fault_gives_up_after_the_last_attempt() {
+    let mut config = Config::default();
+    config.hoppers[0].retry.attempts = 3;
+    let run = run_with(
+        "retry-persistent",
+        FaultPlan {
+            fail_creates: usize::MAX,
+            ..FaultPlan::default()
+        },
+        config,
+    );
+
+    let attempts: Vec<(String, u32)> = run
+        .entries
+        .iter()
+        .map(|e| (e.occasion.clone(), e.attempt))
+        .collect();
+    assert_eq!(
+        vec![
+            (String::from("04:25"), 1),
+            (String::from("04:25"), 2),
+            (String::from("04:25"), 3),
+            (String::from("07:30"), 1),
+            (String::from("07:30"), 2),
+            (String::from("07:30"), 3),
+        ],
+        attempts
+    );
+    assert!(run.entries.iter().all(|e| e.outcome == Outcome::Failed));
+}
+
+#[test]
+fn retries_go_through_the_overfeed_guards() {
+    let mut config = Config::default();
+    config.breaker.max_dispenses = 1;
+    let run = run_with(
+        "retry-guarded",
+        FaultPlan {
+            fail_pulse: Some(3),
+            ..FaultPlan::default()
+        },
+        config,
+    );
+
+    assert_eq!(
+        vec![
+            (String::from("04:25"), Outcome::Failed),
+            (String::from("04:25"), Outcome::BreakerTripped),
+            (String::from("07:30"), Outcome::BreakerTripped),
+        ],
+        outcomes(&run.entries)
+    );
+}
+
+#[test]
+fn servo_without_pwm_fails_without_using_up_the_guards() {
+    with_files("no-pwm", |files| {
+        let clock = VirtualClock::new(Local.ymd(2019, 9, 2).and_hms(4, 25, 0));
+        let mut config = Config::default();
+        config.breaker.max_dispenses = 1;
+        let hopper = config.hopper(0);
+        let servo = Servo::new(
+            SERVO1_LIMITS,
+            PULSE_CLOSED_US,
+            PULSE_OPEN_US,
+            PULSE_PASSED_US,
+            hopper.motion,
+            None,
+        );
+        let request = FeedRequest {
+            trigger: history::Trigger::Manual,
+            occasion: String::from("04:25"),
+            requested_ms: 1000,
+            jiggle: hopper.jiggle.clone(),
+            attempt: 1,
+        };
+
+        for _ in 0..2 {
+            let report = guarded_feed(&servo, &hopper, &request, &config, &files, &clock).unwrap();
+            assert_eq!(
+                Some(FeedError::HardwareUnavailable {
+                    reason: String::from("no PWM for servo1")
+                }),
+                report.error
+            );
+        }
+
+        let entries = history::load(&files.history).unwrap();
+        assert!(entries.iter().all(|e| e.outcome == Outcome::Failed));
+        let state = picat::state::current(&files.state);
+        assert_eq!(0, state.rations.dispensed_today("servo1"));
+        assert!(!state.breaker.is_tripped());
+    })
+}
+
+#[test]
+fn feeds_are_capped_at_the_hoppers_max_open_ms() {
+    let mut config = without_retries();
+    config.hoppers[0].max_open_ms = 200;
+    let run = run_with("max-open", FaultPlan::default(), config);
+
+    assert!(run.entries.iter().all(|e| e.outcome == Outcome::Fed));
+    assert!(run.entries.iter().all(|e| e.requested_ms == 320));
+    assert!(run.entries.iter().all(|e| e.open_ms == 200));
+}
@@ -0,0 +1,147 @@
+//! Golden traces of the lid movements `feed_cat` commands. A change to the
+//! open, jiggle or close behavior shows up as a diff of the files in
+//! `tests/golden`. Run with `UPDATE_GOLDEN=1` to rewrite them after an
+//! intended change.
+
+use chrono::prelude::*;
+use rppal::pwm::Channel;
+use std::fs;
+use std::path::Path;
+use std::time::Duration;
+
+use picat::clock::VirtualClock;
+use picat::feeder::feed_cat;
+use picat::hardware::{
+    PULSE_CLOSED_US, PULSE_OPEN_US, PULSE_PASSED_US, SAFE_STATE_TRAVEL_MS, SERVO1_LIMITS,
+};
+use picat::jiggle::{JigglePattern, JiggleStep, Position, Shake};
+use picat::motion::{Easing, MotionProfile};
+use picat::report::FeedReport;
+use picat::safe_state::SafeState;
+use picat::servo::Servo;
+use picat::trace::{Sample, Trace, TracingActuator};
+
+fn start() -> DateTime<Local> {
+    Local.ymd(2019, 9, 2).and_hms(4, 25, 0)
+}
+
+/// One line per sample, timed from the start of the feed.
+fn format_trace(samples: &[Sample]) -> String {
+    samples
+        .iter()
+        .map(|sample| {
+            format!(
+                "{:>6} ms  pwm{}  {} us  {}\n",
+                (sample.time - start()).num_milliseconds(),
+                sample.channel,
+                sample.pulse_us,
+                if sample.enabled { "on" } else { "off" }
+            )
+        })
+        .collect()
+}
+
+fn assert_golden(name: &str, samples: &[Sample]) {
+    let path = Path::new(env!("CARGO_MANIFEST_DIR"))
+        .join("tests/golden")
+        .join(format!("{}.trace", name));
+    let actual = format_trace(samples);
+    if std::env::var_os("UPDATE_GOLDEN").is_some() {
+        fs::write(&path, &actual).unwrap();
+        return;
+    }
+    let expected = fs::read_to_string(&path)
+        .unwrap_or_else(|e| panic!("{}: {}, run with UPDATE_GOLDEN=1", path.display(), e));
+    assert_eq!(expected, actual, "{} differs from the golden trace", name);
+}
+
+/// Feeds once from servo 1, optionally engaging the safe state afterwards as
+/// `feed_occasion` does, and returns the report and what the servo was told
+/// to do.
+fn trace_feed(
+    motion: MotionProfile,
+    feed_time: u64,
+    jiggle: &JigglePattern,
+    then_safe_state: bool,
+) -> (FeedReport, Vec<Sample>) {
+    let clock = VirtualClock::new(start());
+    let trace = Trace::new();
+    let actuator = TracingActuator::new(&trace, &clock, Channel::Pwm0);
+    let servo = Servo::new(
+        SERVO1_LIMITS,
+        PULSE_CLOSED_US,
+        PULSE_OPEN_US,
+        PULSE_PASSED_US,
+        motion,
+        Some(&actuator),
+    )
+    .with_clock(&clock);
+
+    let report = feed_cat(&servo, "servo1", feed_time, feed_time, jiggle, &clock);
+    assert_eq!(None, report.error);
+    if then_safe_state {
+        SafeState::new(vec![&servo], Duration::from_millis(SAFE_STATE_TRAVEL_MS)).engage();
+    }
+    (report, trace.samples())
+}
+
+#[test]
+fn default_jiggle() {
+    let (report, samples) = trace_feed(
+        MotionProfile::default(),
+        320,
+        &JigglePattern::default(),
+        false,
+    );
+    assert_golden("default_jiggle", &samples);
+    assert_eq!(3, report.jiggle_cycles);
+    assert_eq!(1520, report.elapsed_ms);
+}
+
+#[test]
+fn default_jiggle_then_safe_state() {
+    let (_, samples) = trace_feed(
+        MotionProfile::default(),
+        320,
+        &JigglePattern::default(),
+        true,
+    );
+    assert_golden("default_jiggle_then_safe_state", &samples);
+}
+
+#[test]
+fn without_jiggle() {
+    let jiggle = JigglePattern {
+        pre_open: None,
+        after_open: None,
+    };
+    let (_, samples) = trace_feed(MotionProfile::default(), 320, &jiggle, false);
+    assert_golden("without_jiggle", &samples);
+}
+
+#[test]
+fn eased_with_pre_open_shake() {
+    let motion = MotionProfile {
+        easing: Easing::EaseInOut,
+        duration_ms: 100,
+        step_ms: 20,
+    };
+    let jiggle = JigglePattern {
+        pre_open: Some(Shake {
+            repetitions: 2,
+            steps: vec![
+                JiggleStep {
+                    position: Position::Pulse(2500),
+                    dwell_ms: 50,
+                },
+                JiggleStep {
+                    position: Position::Closed,
+                    dwell_ms: 50,
+                },
+            ],
+        }),
+        after_open: JigglePattern::default().after_open,
+    };
+    let (_, samples) = trace_feed(motion, 500, &jiggle, false);
+    assert_golden("eased_with_pre_open_shake", &samples);
+}
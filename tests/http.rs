@@ -0,0 +1,477 @@
+//! Talks HTTP to the API on a local port, answered by the daemon's own
+//! controls with a worker feeding on traced hardware.
+
+mod common;
+
+use chrono::prelude::*;
+use std::io::prelude::*;
+use std::net::{SocketAddr, TcpStream};
+use std::sync::{mpsc, Arc, Mutex};
+use std::thread;
+
+use common::in_scratch_dir;
+use picat::api::{self, HttpConfig};
+use picat::clock::{SystemClock, VirtualClock};
+use picat::config::Config;
+use picat::control;
+use picat::fault::{FaultPlan, FaultyHardware};
+use picat::history::{self, Outcome, Trigger};
+use picat::schedule::Schedule;
+use picat::scheduler::Controls;
+use picat::status::Status;
+use picat::trace::Trace;
+use picat::{actuation, persistant_schedule_storage, state, Files};
+
+fn http_config(token: Option<&str>) -> HttpConfig {
+    HttpConfig {
+        address: String::from("127.0.0.1:0"),
+        token: token.map(String::from),
+    }
+}
+
+/// Runs `f` with the API served on a free port, keeping its files in a
+/// directory of their own.
+fn with_api<R>(name: &str, f: impl FnOnce(SocketAddr, &Files) -> R) -> R {
+    serve_api(
+        name,
+        http_config(None),
+        Some(FaultPlan::default()),
+        |address, files, _| f(address, files),
+    )
+}
+
+/// Serves the API like the daemon does. Commands are carried out by a
+/// worker on hardware failing as `plan` says, or stay in the queue without
+/// one.
+fn serve_api<R>(
+    name: &str,
+    config: HttpConfig,
+    plan: Option<FaultPlan>,
+    f: impl FnOnce(SocketAddr, &Files, &actuation::Queue) -> R,
+) -> R {
+    in_scratch_dir(&format!("http-{}", name), |dir| {
+        let files = Files::in_dir(dir);
+        let shared_config = Arc::new(Mutex::new(Config::default()));
+        let status = Arc::new(Mutex::new(Status::new()));
+        let queue = actuation::Queue::new();
+        let worker = plan.map(|plan| {
+            let worker = actuation::Worker {
+                files: files.clone(),
+                config: shared_config.clone(),
+                status: status.clone(),
+            };
+            let queue = queue.clone();
+            thread::spawn(move || {
+                let clock = VirtualClock::new(Local::now());
+                let trace = Trace::new();
+                worker.run(&queue, &clock, &FaultyHardware::new(&trace, &clock, plan));
+            })
+        });
+        let _stopping = Stopping(queue.clone(), worker);
+        let mut controls = Controls::new(
+            files.clone(),
+            shared_config,
+            Schedule::new(),
+            status,
+            queue.clone(),
+        );
+        let (calls, received) = mpsc::channel::<control::Call>();
+        thread::spawn(move || {
+            for call in received {
+                controls.answer(call, &SystemClock);
+            }
+        });
+        let address = api::serve(&config, files.clone(), calls).unwrap();
+        f(address, &files, &queue)
+    })
+}
+
+/// Stops the worker before its files are removed, also when a test fails.
+struct Stopping(actuation::Queue, Option<thread::JoinHandle<()>>);
+
+impl Drop for Stopping {
+    fn drop(&mut self) {
+        self.0.close();
+        if let Some(worker) = self.1.take() {
+            let _ = worker.join();
+        }
+    }
+}
+
+/// Sends one request and returns the status code, content type and body.
+fn http(address: SocketAddr, method: &str, path: &str, body: &str) -> (u16, String, String) {
+    http_with_headers(address, method, path, "", body)
+}
+
+/// Sends one request with `headers`, each ending in CRLF.
+fn http_with_headers(
+    address: SocketAddr,
+    method: &str,
+    path: &str,
+    headers: &str,
+    body: &str,
+) -> (u16, String, String) {
+    let mut stream = TcpStream::connect(address).unwrap();
+    write!(
+        stream,
+        "{} {} HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n{}Content-Length: {}\r\n\r\n{}",
+        method,
+        path,
+        headers,
+        body.len(),
+        body
+    )
+    .unwrap();
+    let mut response = String::new();
+    stream.read_to_string(&mut response).unwrap();
+    let (head, body) = response.split_at(response.find("\r\n\r\n").unwrap() + 4);
+    let status = head[9..12].parse().unwrap();
+    let content_type = head
+        .lines()
+        .find(|line| line.to_lowercase().starts_with("content-type:"))
+        .map(|line| line[13..].trim().to_string())
+        .unwrap_or_default();
+    (status, content_type, body.to_string())
+}
+
+fn json(body: &str) -> serde_json::Value {
+    serde_json::from_str(body).unwrap()
+}
+
+fn occasion(time: &str, weekdays: &[u32], opened_ms: u64) -> String {
+    serde_json::json!({
+        "enabled": true,
+        "time": format!("1970-01-01T{}:00+00:00", time),
+        "enabled_weekdays": weekdays,
+        "opened_time_servo1": opened_ms,
+        "opened_time_servo2": 280,
+    })
+    .to_string()
+}
+
+fn saved_schedule(files: &Files) -> Vec<(String, u64)> {
+    persistant_schedule_storage::load(&files.schedule)
+        .unwrap()
+        .get_times()
+        .iter()
+        .map(|o| {
+            (
+                o.time.with_timezone(&Utc).format("%H:%M").to_string(),
+                o.opened_time_servo1,
+            )
+        })
+        .collect()
+}
+
+#[test]
+fn occasions_are_created_updated_and_deleted() {
+    with_api("crud", |address, files| {
+        assert_eq!(json("[]"), json(&http(address, "GET", "/occasions", "").2));
+
+        let (status, _, _) = http(address, "POST", "/occasions", &occasion("09:00", &[1], 300));
+        assert_eq!(201, status);
+        assert_eq!(vec![(String::from("09:00"), 300)], saved_schedule(files));
+        let listed = json(&http(address, "GET", "/occasions", "").2);
+        assert_eq!(300, listed[0]["opened_time_servo1"]);
+
+        let id = Local
+            .from_utc_datetime(&NaiveDate::from_ymd(1970, 1, 1).and_hms(9, 0, 0))
+            .format("%H:%M")
+            .to_string();
+        let path = format!("/occasions/{}", id);
+        let (status, _, _) = http(address, "PUT", &path, &occasion("09:00", &[1, 2], 450));
+        assert_eq!(200, status);
+        assert_eq!(vec![(String::from("09:00"), 450)], saved_schedule(files));
+
+        // every byte escaped, as some clients do
+        let escaped: String = id.bytes().map(|b| format!("%{:02X}", b)).collect();
+        assert_eq!(
+            200,
+            http(address, "DELETE", &format!("/occasions/{}", escaped), "").0
+        );
+        assert!(saved_schedule(files).is_empty());
+        let (status, _, body) = http(address, "DELETE", &path, "");
+        assert_eq!(404, status);
+        assert_eq!(format!("No occasion at {}", id), json(&body)["error"]);
+    })
+}
+
+#[test]
+fn invalid_occasions_are_refused_and_not_saved() {
+    with_api("invalid", |address, files| {
+        let (status, _, body) = http(address, "POST", "/occasions", &occasion("09:00", &[], 0));
+        assert_eq!(400, status);
+        let error = json(&body)["error"].as_str().unwrap().to_string();
+        assert!(error.contains("is not enabled on any weekday"), "{}", error);
+        assert!(error.contains("never opens servo 1"), "{}", error);
+
+        let (status, _, _) = http(address, "POST", "/occasions", "{\"time\": 9}");
+        assert_eq!(400, status);
+        assert!(!std::path::Path::new(&files.schedule).exists());
+    })
+}
+
+#[test]
+fn failures_to_persist_are_internal_errors() {
+    with_api("persist", |address, files| {
+        // nothing can be renamed over a directory
+        std::fs::create_dir(&files.schedule).unwrap();
+        let (status, _, body) = http(address, "POST", "/occasions", &occasion("09:00", &[1], 300));
+        assert_eq!(500, status);
+        let error = json(&body)["error"].as_str().unwrap().to_string();
+        assert!(error.starts_with("Failed to persist schedule"), "{}", error);
+    })
+}
+
+#[test]
+fn feeding_and_status_go_to_the_feeder_loop() {
+    with_api("feed", |address, files| {
+        let (status, _, body) = http(address, "POST", "/feed", "");
+        assert_eq!(400, status);
+        assert_eq!(
+            "No feeds scheduled to take the amount from",
+            json(&body)["error"]
+        );
+
+        let (status, _, body) = http(address, "POST", "/feed", "{\"ms\": 500}");
+        assert_eq!(200, status);
+        assert_eq!(500, json(&body)["requested_ms"]);
+        assert_eq!(500, json(&body)["open_ms"]);
+        assert_eq!(json("null"), json(&body)["error"]);
+        let fed = history::load(&files.history).unwrap();
+        assert_eq!(
+            vec![(Trigger::Manual, Outcome::Fed, 500)],
+            fed.iter()
+                .map(|e| (e.trigger, e.outcome, e.open_ms))
+                .collect::<Vec<_>>()
+        );
+
+        let (status, content_type, body) = http(address, "GET", "/status", "");
+        assert_eq!(200, status);
+        assert_eq!("application/json", content_type);
+        let report = json(&body)["status"].as_str().unwrap().to_string();
+        assert!(report.contains("(fed)"), "{}", report);
+
+        for ms in ["0", "-1", "\"300\""].iter() {
+            let (status, _, body) = http(address, "POST", "/feed", &format!("{{\"ms\": {}}}", ms));
+            assert_eq!(400, status, "{}", ms);
+            assert!(json(&body)["error"]
+                .as_str()
+                .unwrap()
+                .starts_with("Invalid feed"));
+        }
+        let (status, _, body) = http(address, "POST", "/feed", "{\"ms\": 3001}");
+        assert_eq!(400, status);
+        assert!(json(&body)["error"]
+            .as_str()
+            .unwrap()
+            .ends_with("longer than its max_open_ms of 3000"));
+        assert_eq!(1, history::load(&files.history).unwrap().len());
+
+        assert_eq!(200, http(address, "POST", "/pause", "").0);
+        assert_eq!(true, json(&http(address, "GET", "/status", "").2)["paused"]);
+        assert_eq!(200, http(address, "POST", "/resume", "").0);
+        assert_eq!(
+            false,
+            json(&http(address, "GET", "/status", "").2)["paused"]
+        );
+
+        let (status, _, body) = http(address, "POST", "/skip-next", "");
+        assert_eq!(400, status);
+        assert_eq!("No feeds scheduled", json(&body)["error"]);
+        http(
+            address,
+            "POST",
+            "/occasions",
+            &occasion("09:00", &[1, 2, 3, 4, 5, 6, 7], 300),
+        );
+        let (status, _, body) = http(address, "POST", "/skip-next", "");
+        assert_eq!(200, status);
+        assert!(json(&body)["message"]
+            .as_str()
+            .unwrap()
+            .starts_with("Skipping the feed at"));
+        let (status, _, body) = http(address, "POST", "/feed", "");
+        assert_eq!(200, status);
+        assert_eq!(300, json(&body)["requested_ms"]);
+    })
+}
+
+#[test]
+fn failed_feeds_are_not_answered_as_success() {
+    for (name, plan, expected) in [
+        (
+            "feed-pulse",
+            FaultPlan {
+                fail_pulse: Some(3),
+                ..FaultPlan::default()
+            },
+            500,
+        ),
+        (
+            "feed-pwm",
+            FaultPlan {
+                fail_creates: 100,
+                ..FaultPlan::default()
+            },
+            503,
+        ),
+    ] {
+        serve_api(name, http_config(None), Some(plan), |address, _, _| {
+            let (status, _, body) = http(address, "POST", "/feed", "{\"ms\": 500}");
+            assert_eq!(expected, status, "{}", body);
+            assert!(json(&body)["error"].is_object(), "{}", body);
+        })
+    }
+}
+
+#[test]
+fn tripped_breaker_is_reset() {
+    with_api("breaker", |address, files| {
+        let tripped_at = Local.ymd(2019, 9, 2).and_hms(4, 25, 0);
+        state::update(&files.state, |state| state.breaker.trip(tripped_at));
+
+        let (status, _, body) = http(address, "POST", "/breaker/reset", "");
+        assert_eq!(200, status);
+        assert_eq!(
+            format!("Circuit breaker tripped at {} reset", tripped_at),
+            json(&body)["message"]
+        );
+        assert!(!state::current(&files.state).breaker.is_tripped());
+        assert_eq!(
+            "Circuit breaker was not tripped",
+            json(&http(address, "POST", "/breaker/reset", "").2)["message"]
+        );
+    })
+}
+
+#[test]
+fn waiting_commands_are_listed_and_cancelled() {
+    serve_api("queue", http_config(None), None, |address, _, queue| {
+        let _waiting = queue.submit(actuation::Command::Close);
+        let (status, _, body) = http(address, "GET", "/queue", "");
+        assert_eq!(200, status);
+        assert_eq!(
+            json("[{\"id\": 1, \"command\": \"safety close\"}]"),
+            json(&body)
+        );
+
+        assert_eq!(200, http(address, "DELETE", "/queue/1", "").0);
+        assert_eq!(json("[]"), json(&http(address, "GET", "/queue", "").2));
+        let (status, _, body) = http(address, "DELETE", "/queue/1", "");
+        assert_eq!(404, status);
+        assert_eq!("No command 1 waiting", json(&body)["error"]);
+        assert_eq!(400, http(address, "DELETE", "/queue/first", "").0);
+    })
+}
+
+#[test]
+fn history_is_filtered_and_formatted_like_the_cli() {
+    with_api("history", |address, files| {
+        for day in [1, 2].iter() {
+            let entry = history::Entry {
+                time: Local.ymd(2019, 9, *day).and_hms(4, 25, 0).into(),
+                trigger: Trigger::Scheduled,
+                occasion: String::from("04:25"),
+                hopper: String::from("servo1"),
+                requested_ms: 320,
+                open_ms: 320,
+                outcome: Outcome::Fed,
+                error: None,
+                attempt: 1,
+                elapsed_ms: 1520,
+                jiggle_cycles: 3,
+            };
+            history::append(&files.history, &entry).unwrap();
+        }
+
+        let all = json(&http(address, "GET", "/history", "").2);
+        assert_eq!(2, all.as_array().unwrap().len());
+        let (status, content_type, body) =
+            http(address, "GET", "/history?from=2019-09-02&format=csv", "");
+        assert_eq!(200, status);
+        assert_eq!("text/csv", content_type);
+        assert_eq!(2, body.lines().count());
+        assert!(body
+            .lines()
+            .nth(1)
+            .unwrap()
+            .starts_with("2019-09-02T04:25:00"));
+
+        let escaped = json(&http(address, "GET", "/history?from=2019%2D09%2D02", "").2);
+        assert_eq!(1, escaped.as_array().unwrap().len());
+        assert_eq!(400, http(address, "GET", "/history?from=2019%2", "").0);
+
+        let (status, _, body) = http(address, "GET", "/history?since=yesterday", "");
+        assert_eq!(400, status);
+        assert_eq!("Unknown option --since", json(&body)["error"]);
+    })
+}
+
+#[test]
+fn config_is_checked_and_saved() {
+    with_api("config", |address, files| {
+        let mut config = json(&http(address, "GET", "/config", "").2);
+        assert_eq!(3, config["breaker"]["max_dispenses"]);
+
+        config["breaker"]["max_dispenses"] = serde_json::json!(5);
+        let (status, _, _) = http(address, "PUT", "/config", &config.to_string());
+        assert_eq!(200, status);
+        let saved = picat::config::load(&files.config).unwrap();
+        assert_eq!(5, saved.breaker.max_dispenses);
+        assert_eq!(
+            5,
+            json(&http(address, "GET", "/config", "").2)["breaker"]["max_dispenses"]
+        );
+
+        let (status, _, _) = http(address, "PUT", "/config", "{\"hoppers\": 1}");
+        assert_eq!(400, status);
+    })
+}
+
+#[test]
+fn token_is_required_when_set() {
+    let plan = Some(FaultPlan::default());
+    serve_api(
+        "token",
+        http_config(Some("s3cret")),
+        plan,
+        |address, _, _| {
+            assert_eq!(401, http(address, "GET", "/status", "").0);
+            let wrong = "Authorization: Bearer guess\r\n";
+            assert_eq!(
+                401,
+                http_with_headers(address, "GET", "/status", wrong, "").0
+            );
+            let right = "Authorization: Bearer s3cret\r\n";
+            assert_eq!(
+                200,
+                http_with_headers(address, "GET", "/status", right, "").0
+            );
+        },
+    )
+}
+
+#[test]
+fn other_addresses_than_loopback_need_a_token() {
+    let (calls, _received) = mpsc::channel::<control::Call>();
+    let open = HttpConfig {
+        address: String::from("0.0.0.0:0"),
+        token: None,
+    };
+    assert!(api::serve(&open, Files::default(), calls.clone()).is_err());
+    let guarded = HttpConfig {
+        token: Some(String::from("s3cret")),
+        ..open
+    };
+    assert!(api::serve(&guarded, Files::default(), calls).is_ok());
+}
+
+#[test]
+fn unknown_endpoints_are_not_found() {
+    with_api("unknown", |address, _| {
+        assert_eq!(404, http(address, "GET", "/index.html", "").0);
+        assert_eq!(404, http(address, "PATCH", "/occasions", "").0);
+        assert_eq!(400, http(address, "GET", "/occasions/%ZZ", "").0);
+    })
+}
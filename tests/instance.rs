@@ -0,0 +1,66 @@
+//! Starts the daemon for real, to see that one started from another working
+//! directory still finds its lock.
+
+mod common;
+
+use std::process::{Command, Stdio};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use common::in_scratch_dir;
+use picat::{Files, LOCK_FILE_NAME, SOCKET_FILE_NAME};
+
+#[test]
+fn second_daemon_from_another_directory_is_refused() {
+    in_scratch_dir("instance", |dir| {
+        let shared = dir.join("state");
+        let first_dir = dir.join("first");
+        let second_dir = dir.join("second");
+        std::fs::create_dir_all(&first_dir).unwrap();
+        std::fs::create_dir_all(&second_dir).unwrap();
+        let daemon = |cwd: &std::path::Path| {
+            let mut command = Command::new(env!("CARGO_BIN_EXE_picat"));
+            command.current_dir(cwd).env("PICAT_DIR", &shared);
+            command
+        };
+
+        let mut first = daemon(&first_dir)
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .spawn()
+            .unwrap();
+        let started = Instant::now();
+        while !shared.join(SOCKET_FILE_NAME).exists() && started.elapsed() < Duration::from_secs(10)
+        {
+            thread::sleep(Duration::from_millis(20));
+        }
+        let second = daemon(&second_dir).output().unwrap();
+        // stopped before anything is asserted, it must not outlive the test
+        unsafe { libc::kill(first.id() as i32, libc::SIGTERM) };
+        first.wait().unwrap();
+
+        let stderr = String::from_utf8_lossy(&second.stderr);
+        assert!(
+            stderr.contains(&format!("Another picat (pid {})", first.id())),
+            "{}",
+            stderr
+        );
+        assert!(shared.join(LOCK_FILE_NAME).exists());
+        assert!(!second_dir.join(LOCK_FILE_NAME).exists());
+        assert!(!second_dir.join(picat::STATE_FILE_NAME).exists());
+    })
+}
+
+#[test]
+fn lock_and_socket_are_in_the_state_directory() {
+    let files = Files::default();
+    let dir = picat::state_dir();
+
+    assert!(dir.is_absolute());
+    assert_eq!(dir.join(LOCK_FILE_NAME).to_str(), Some(files.lock.as_str()));
+    assert_eq!(
+        dir.join(SOCKET_FILE_NAME).to_str(),
+        Some(files.socket.as_str())
+    );
+    assert_eq!(picat::SCHEDULE_FILE_NAME, files.schedule);
+}
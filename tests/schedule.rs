@@ -0,0 +1,29 @@
+mod common;
+
+use chrono::prelude::*;
+
+use common::{in_scratch_dir, occasion};
+use picat::persistant_schedule_storage;
+use picat::schedule::Schedule;
+
+#[test]
+fn stored_schedule_fires_the_same() {
+    let mut schedule = Schedule::new();
+    schedule.push(occasion(4, 25, vec![Weekday::Mon, Weekday::Sun]));
+    schedule.push(occasion(19, 30, vec![Weekday::Sun]));
+
+    let loaded = in_scratch_dir("schedule", |dir| {
+        let path = dir.join(picat::SCHEDULE_FILE_NAME);
+        let path = path.to_str().unwrap();
+        persistant_schedule_storage::save(path, &schedule).unwrap();
+        persistant_schedule_storage::load(path).unwrap()
+    });
+
+    let sunday = Local.ymd(2019, 9, 1).and_hms(19, 30, 0);
+    assert_eq!("19:30", loaded.contains(sunday).unwrap().id());
+    assert_eq!(
+        Some(Local.ymd(2019, 9, 2).and_hms(4, 25, 0)),
+        loaded.next_after(sunday)
+    );
+    assert!(loaded.lint().is_empty());
+}
@@ -0,0 +1,141 @@
+mod common;
+
+use chrono::prelude::*;
+use std::sync::Once;
+
+use common::{every_day, in_scratch_dir, occasion, stand_in};
+use picat::clock::{Clock, VirtualClock};
+use picat::journal::Journal;
+use picat::persistant_schedule_storage;
+use picat::schedule::{Occasion, Schedule};
+use picat::scheduler;
+use picat::Files;
+
+static TIMEZONE: Once = Once::new();
+
+/// Runs the tests in a timezone with daylight saving time. Has to happen
+/// before anything asks for the local time.
+fn in_stockholm() {
+    TIMEZONE.call_once(|| std::env::set_var("TZ", "Europe/Stockholm"));
+}
+
+/// Runs the scheduler from `from` until `until` and returns the times it fed.
+fn run(
+    name: &str,
+    schedule: Schedule,
+    from: DateTime<Local>,
+    until: DateTime<Local>,
+) -> Vec<DateTime<Local>> {
+    in_scratch_dir(name, |dir| {
+        let clock = VirtualClock::new(from);
+        let mut journal = Journal::open(&Files::in_dir(dir).journal).unwrap();
+        let mut fed = Vec::new();
+        let mut host = stand_in(schedule, until, |_, _, clock: &dyn Clock| {
+            fed.push(clock.now());
+            None
+        });
+
+        scheduler::run(&clock, &mut journal, &mut host).unwrap();
+        fed
+    })
+}
+
+#[test]
+fn feeds_every_occasion_of_a_week_once() {
+    in_stockholm();
+    let mut schedule = Schedule::new();
+    schedule.push(occasion(4, 25, every_day()));
+    schedule.push(occasion(19, 30, vec![Weekday::Sat, Weekday::Sun]));
+
+    let fed = run(
+        "scheduler-week",
+        schedule,
+        Local.ymd(2019, 9, 2).and_hms(0, 0, 0),
+        Local.ymd(2019, 9, 9).and_hms(0, 0, 0),
+    );
+
+    let mut expected: Vec<DateTime<Local>> = (2..9)
+        .map(|day| Local.ymd(2019, 9, day).and_hms(4, 25, 0))
+        .collect();
+    expected.push(Local.ymd(2019, 9, 7).and_hms(19, 30, 0));
+    expected.push(Local.ymd(2019, 9, 8).and_hms(19, 30, 0));
+    expected.sort();
+    assert_eq!(expected, fed);
+}
+
+#[test]
+fn skips_occasion_in_the_hour_lost_to_dst() {
+    in_stockholm();
+    let mut schedule = Schedule::new();
+    schedule.push(occasion(2, 30, every_day()));
+
+    let fed = run(
+        "scheduler-spring",
+        schedule,
+        Local.ymd(2019, 3, 30).and_hms(0, 0, 0),
+        Local.ymd(2019, 4, 1).and_hms(12, 0, 0),
+    );
+
+    assert_eq!(
+        vec![
+            Local.ymd(2019, 3, 30).and_hms(2, 30, 0),
+            Local.ymd(2019, 4, 1).and_hms(2, 30, 0),
+        ],
+        fed
+    );
+}
+
+#[test]
+fn feeds_once_in_the_hour_repeated_by_dst() {
+    in_stockholm();
+    let mut schedule = Schedule::new();
+    schedule.push(occasion(2, 30, every_day()));
+
+    let fed = run(
+        "scheduler-autumn",
+        schedule,
+        Local.ymd(2019, 10, 27).and_hms(0, 0, 0),
+        Local.ymd(2019, 10, 27).and_hms(12, 0, 0),
+    );
+
+    // the first 02:30, still in summer time
+    assert_eq!(1, fed.len());
+    assert_eq!(Utc.ymd(2019, 10, 27).and_hms(0, 30, 0), fed[0]);
+}
+
+#[test]
+fn reload_keeps_the_schedule_in_use_when_the_file_is_corrupt() {
+    in_scratch_dir("reload", |dir| {
+        let files = Files::in_dir(dir);
+        let mut schedule = Schedule::new();
+        schedule.push(occasion(7, 0, every_day()));
+        persistant_schedule_storage::save(&files.schedule, &schedule).unwrap();
+        let mut config = scheduler::load_config(&files).unwrap();
+        let mut schedule = scheduler::load_schedule(&files).unwrap();
+
+        std::fs::write(&files.schedule, "[{\"time\": \"07:").unwrap();
+        let result = scheduler::reload(&files, &mut config, &mut schedule);
+
+        assert!(result.is_err());
+        assert_eq!(1, schedule.get_times().len());
+        assert_eq!(7, schedule.get_times()[0].time.hour());
+        // the corrupt file is left for the user to fix, not replaced by the default
+        assert_eq!(
+            "[{\"time\": \"07:",
+            std::fs::read_to_string(&files.schedule).unwrap()
+        );
+
+        let mut refused = Schedule::new();
+        refused.push(Occasion {
+            opened_time_servo1: 5000,
+            ..occasion(8, 0, every_day())
+        });
+        persistant_schedule_storage::save(&files.schedule, &refused).unwrap();
+        let result = scheduler::reload(&files, &mut config, &mut schedule);
+
+        assert!(result
+            .unwrap_err()
+            .contains("longer than its max_open_ms of 3000"));
+        assert_eq!(7, schedule.get_times()[0].time.hour());
+    })
+}
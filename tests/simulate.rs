@@ -0,0 +1,59 @@
+mod common;
+
+use chrono::prelude::*;
+
+use common::occasion;
+use picat::config::Config;
+use picat::schedule::Schedule;
+use picat::simulate::{self, Options};
+
+#[test]
+fn simulates_feeds_and_pulses_without_touching_real_state() {
+    let mut schedule = Schedule::new();
+    schedule.push(occasion(4, 25, vec![Weekday::Mon, Weekday::Tue]));
+    schedule.push(occasion(19, 30, vec![Weekday::Wed]));
+    let args: Vec<String> = vec!["--from", "2019-09-02", "--days", "2"]
+        .into_iter()
+        .map(String::from)
+        .collect();
+    let options = Options::parse(&args).unwrap();
+    let cwd = std::env::current_dir().unwrap();
+
+    let timeline = simulate::run(&options, schedule, Config::default()).unwrap();
+
+    assert_eq!(cwd, std::env::current_dir().unwrap());
+    assert!(!cwd.join(picat::STATE_FILE_NAME).exists());
+    assert!(!cwd.join(picat::HISTORY_FILE_NAME).exists());
+    let feeds: Vec<&str> = timeline.lines().filter(|l| l.contains("feed")).collect();
+    assert_eq!(
+        vec![
+            "2019-09-02 04:25:00.000  feed  04:25 servo1, requested 320 ms, opened 320 ms: fed",
+            // the checks run 30 s apart from the end of the previous feed
+            "2019-09-03 04:25:02.000  feed  04:25 servo1, requested 320 ms, opened 320 ms: fed",
+        ],
+        feeds
+    );
+    let first_feed: Vec<&str> = timeline.lines().skip(1).take(3).collect();
+    assert_eq!(
+        vec![
+            "2019-09-02 04:25:00.000  pwm0  2400 us",
+            "2019-09-02 04:25:00.000  pwm0  1850 us",
+            "2019-09-02 04:25:00.320  pwm0  2650 us",
+        ],
+        first_feed
+    );
+    assert!(timeline.lines().last().unwrap().ends_with("pwm0  off"));
+}
+
+#[test]
+fn speed_needs_a_positive_factor() {
+    let args = |speed: &str| -> Vec<String> {
+        vec!["--from", "2019-09-02", "--speed", speed]
+            .into_iter()
+            .map(String::from)
+            .collect()
+    };
+    assert_eq!(Some(1000.0), Options::parse(&args("1000x")).unwrap().speed);
+    assert!(Options::parse(&args("0x")).is_err());
+    assert!(Options::parse(&args("fast")).is_err());
+}